@@ -2,78 +2,107 @@ use {
     ::url::Url,
     model::{
         auction::Auction,
-        order::{OrderClass, OrderKind, OrderStatus, OrderUid, CancellationPayload, OrderCancellations, OrderCreation, BUY_ETH_ADDRESS},
+        order::{OrderUid, CancellationPayload, OrderCancellations, OrderCreation},
         quote::{OrderQuoteRequest, OrderQuoteResponse},
         trade::Trade,
     },
-    crate::models::{CompetitionOrderStatus, NativePriceResponse, TotalSurplus, CompetitionAuction, SolverCompetitionResponse},
-    anyhow::{Context, Result},
-    number::serialization::HexOrDecimalU256,
+    crate::{
+        config::{self, Config},
+        errors::{ConfigError, OrderbookError, PageSizeError},
+        policy::{RateLimiter, RequestPolicy},
+        models::{CompetitionOrderStatus, NativePriceResponse, Order, TotalSurplus, SolverCompetitionResponse},
+    },
+    futures::{stream, Stream, TryStreamExt},
     app_data::{AppDataHash, AppDataDocument},
     shared::url,
-    primitive_types::{H160, U256, H256},
-    serde::{Deserialize, Deserializer, Serialize, Serializer, de},
-    reqwest::Client,
-    hex_literal::hex,
-    serde_with::serde_as,
-    serde_json,
-    ethcontract::{Address},
-    std::{
-        collections::HashMap,
-        time::{Duration, Instant},
-    },
+    primitive_types::H256,
+    reqwest::{Client, RequestBuilder, Response},
+    ethcontract::Address,
+    std::future::Future,
 };
 
-#[serde_as]
-#[derive(Debug, serde::Deserialize, Eq, PartialEq)]
-#[serde(rename_all = "camelCase")]
-struct Order {
-    kind: OrderKind,
-    buy_token: H160,
-    #[serde_as(as = "HexOrDecimalU256")]
-    buy_amount: U256,
-    sell_token: H160,
-    #[serde_as(as = "HexOrDecimalU256")]
-    sell_amount: U256,
-    uid: OrderUid,
-    partially_fillable: bool,
-    #[serde(flatten)]
-    class: OrderClass,
-    // Some if the order is fetched from api/v1/orders/{uid}
-    // None if the order is fetched from api/v1/auction
-    #[serde(default)]
-    status: Option<OrderStatus>,
-}
-
-impl Order {
-    fn is_liquidity_order(&self) -> bool {
-        matches!(self.class, OrderClass::Liquidity)
-    }
-}
+/// Largest `limit` the orderbook accepts for paginated endpoints.
+pub const MAX_PAGE_SIZE: u64 = 1000;
 
 pub struct OrderBookApi {
     base: Url,
     client: Client,
+    policy: RequestPolicy,
+    limiter: Option<RateLimiter>,
 }
 
 impl OrderBookApi {
-    pub fn new(client: Client, base_url: &str) -> Self {
-        Self {
-            base: base_url.parse().unwrap(),
+    /// Creates a client for the orderbook at `base_url`, e.g. one of
+    /// [`crate::urls`].
+    pub fn new(client: Client, base_url: &str) -> Result<Self, ConfigError> {
+        Ok(Self {
+            base: config::parse_base_url(base_url)?,
+            client,
+            policy: RequestPolicy::default(),
+            limiter: None,
+        })
+    }
+
+    /// Creates a client for the orderbook of `config`.
+    pub fn from_config(client: Client, config: &Config) -> Result<Self, ConfigError> {
+        Ok(Self {
+            base: config.base_url()?,
             client,
+            policy: RequestPolicy::default(),
+            limiter: None,
+        })
+    }
+
+    /// Sends all requests according to `policy` instead of the default one.
+    pub fn with_policy(mut self, policy: RequestPolicy) -> Self {
+        self.limiter = policy.rate_limit.map(RateLimiter::new);
+        self.policy = policy;
+        self
+    }
+
+    /// Sends `request` to `endpoint`, retrying it as the policy allows.
+    ///
+    /// Only for requests that are safe to repeat: reads, quotes and the
+    /// registration of app data, which is keyed by its hash.
+    ///
+    /// Returns the response of the last attempt, which is unsuccessful if the
+    /// retries are exhausted or the failure is not retried.
+    async fn send(&self, endpoint: &str, request: RequestBuilder) -> reqwest::Result<Response> {
+        let request = request.timeout(self.policy.timeout(endpoint));
+        let mut attempt = 0;
+        loop {
+            if let Some(limiter) = &self.limiter {
+                limiter.acquire().await;
+            }
+            // Request bodies are always serialized in memory, so they can be
+            // cloned.
+            let outcome = request.try_clone().expect("streaming body").send().await;
+            match self.policy.retry_delay(attempt, &outcome) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return outcome,
+            }
+            attempt += 1;
         }
     }
 
+    /// Sends `request` to `endpoint` exactly once, for requests that are not
+    /// safe to repeat.
+    async fn send_once(&self, endpoint: &str, request: RequestBuilder) -> reqwest::Result<Response> {
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire().await;
+        }
+        request.timeout(self.policy.timeout(endpoint)).send().await
+    }
+
     pub async fn solvable_orders(&self) -> reqwest::Result<Vec<Order>> {
         #[derive(serde::Deserialize)]
         struct Auction {
             orders: Vec<Order>,
         }
         let url = url::join(&self.base, "api/v1/auction");
+        let request = self.client.get(url);
         let auction: Auction = self
-            .client
-            .get(url)
-            .send()
+            .send("api/v1/auction", request)
             .await?
             .error_for_status()?
             .json()
@@ -82,23 +111,27 @@ impl OrderBookApi {
     }
 
     //post an order
-    pub async fn create_order(&self, order: &OrderCreation) -> reqwest::Result<OrderUid> {
-        let url = url::join(&self.base, &format!("api/v1/orders"));
-        self.client
-            .post(url)
-            .send()
-            .await?
-            .error_for_status()?
-            .json()
-            .await
+    //
+    // Never retried: the orderbook may have accepted the order of a request
+    // that timed out or failed, and a retry would be rejected as a duplicate.
+    pub async fn create_order(&self, order: &OrderCreation) -> Result<OrderUid, OrderbookError> {
+        let url = url::join(&self.base, "api/v1/orders");
+        let request = self.client.post(url).json(order);
+        let response = self.send_once("api/v1/orders", request).await?;
+        if !response.status().is_success() {
+            return Err(OrderbookError::from_response(response).await);
+        }
+        Ok(response.json().await?)
     }
     //delete an order
+    //
+    // Never retried: the orderbook may have cancelled the order for a request
+    // that timed out or failed, and a retry would be rejected because the
+    // order is cancelled already.
     pub async fn cancel_order(&self, uid: &OrderUid, cancellation: &CancellationPayload) -> reqwest::Result<()> {
         let url = url::join(&self.base, &format!("api/v1/orders/{uid}"));
-        self.client
-            .delete(url)
-            .json(cancellation)
-            .send()
+        let request = self.client.delete(url).json(cancellation);
+        self.send_once("api/v1/orders/{uid}", request)
             .await?
             .error_for_status()?;
         Ok(())
@@ -106,10 +139,8 @@ impl OrderBookApi {
     //delete an order
     pub async fn cancel_orders(&self, cancellations: &OrderCancellations) -> reqwest::Result<()> {
         let url = url::join(&self.base, &format!("api/v1/orders"));
-        self.client
-            .delete(url)
-            .json(cancellations)
-            .send()
+        let request = self.client.delete(url).json(cancellations);
+        self.send_once("api/v1/orders", request)
             .await?
             .error_for_status()?;
         Ok(())
@@ -117,9 +148,8 @@ impl OrderBookApi {
 
     pub async fn get_order(&self, uid: &OrderUid) -> reqwest::Result<Order> {
         let url = url::join(&self.base, &format!("api/v1/orders/{uid}"));
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v1/orders/{uid}", request)
             .await?
             .error_for_status()?
             .json()
@@ -128,9 +158,8 @@ impl OrderBookApi {
     //get the status of an order 
     pub async fn get_order_status(&self, uid: &OrderUid) -> reqwest::Result<CompetitionOrderStatus> {
         let url = url::join(&self.base, &format!("api/v1/orders/{uid}/status"));
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v1/orders/{uid}/status", request)
             .await?
             .error_for_status()?
             .json()
@@ -140,9 +169,8 @@ impl OrderBookApi {
    // Transaction endpoints
     pub async fn get_orders_by_tx(&self, tx_hash: &H256) -> reqwest::Result<Vec<Order>> {
         let url = url::join(&self.base, &format!("api/v1/transactions/{:x}/orders", tx_hash));
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v1/transactions/{tx_hash}/orders", request)
             .await?
             .error_for_status()?
             .json()
@@ -152,10 +180,11 @@ impl OrderBookApi {
     // Trades endpoints
     pub async fn get_trades_by_owner(&self, owner: &Address) -> reqwest::Result<Vec<Trade>> {
         let url = url::join(&self.base, "api/v1/trades");
-        self.client
+        let request = self
+            .client
             .get(url)
-            .query(&[("owner", format!("{:x}", owner))])
-            .send()
+            .query(&[("owner", format!("{:x}", owner))]);
+        self.send("api/v1/trades", request)
             .await?
             .error_for_status()?
             .json()
@@ -164,22 +193,51 @@ impl OrderBookApi {
 
     pub async fn get_trades_by_order(&self, order_uid: &OrderUid) -> reqwest::Result<Vec<Trade>> {
         let url = url::join(&self.base, "api/v1/trades");
-        self.client
+        let request = self
+            .client
             .get(url)
-            .query(&[("orderUid", order_uid.to_string())])
-            .send()
+            .query(&[("orderUid", order_uid.to_string())]);
+        self.send("api/v1/trades", request)
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+
+    /// Fetches one page of the trades of `owner`.
+    pub async fn get_trades_page(&self, owner: &Address, offset: u64, limit: u64) -> reqwest::Result<Vec<Trade>> {
+        let url = url::join(&self.base, "api/v2/trades");
+        let request = self.client.get(url).query(&[
+            ("owner", format!("{:x}", owner)),
+            ("offset", offset.to_string()),
+            ("limit", limit.to_string()),
+        ]);
+        self.send("api/v2/trades", request)
             .await?
             .error_for_status()?
             .json()
             .await
     }
 
+    /// Streams all trades of `owner`, fetching `page_size` of them per request.
+    ///
+    /// See [`Self::stream_user_orders`] for how pages are fetched and which
+    /// page sizes are rejected.
+    pub fn stream_trades_by_owner(
+        &self,
+        owner: Address,
+        page_size: u64,
+    ) -> Result<impl Stream<Item = reqwest::Result<Trade>> + '_, PageSizeError> {
+        paginate(page_size, move |offset, limit| async move {
+            self.get_trades_page(&owner, offset, limit).await
+        })
+    }
+
     // Auction endpoints
     pub async fn get_auction(&self) -> reqwest::Result<Auction> {
         let url = url::join(&self.base, "api/v1/auction");
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v1/auction", request)
             .await?
             .error_for_status()?
             .json()
@@ -197,22 +255,38 @@ impl OrderBookApi {
             query.push(("limit", limit.to_string()));
         }
         
-        self.client
-            .get(url)
-            .query(&query)
-            .send()
+        let request = self.client.get(url).query(&query);
+        self.send("api/v1/account/{owner}/orders", request)
             .await?
             .error_for_status()?
             .json()
             .await
     }
 
+    /// Streams all orders of `owner`, fetching `page_size` of them per request.
+    ///
+    /// Pages are only requested once the previous one was consumed, so a slow
+    /// consumer slows down the requests instead of buffering the history. The
+    /// stream ends after the first page that is not full and after the first
+    /// error.
+    ///
+    /// Returns a [`PageSizeError`] if `page_size` is zero or larger than
+    /// [`MAX_PAGE_SIZE`], since the orderbook would reject every page.
+    pub fn stream_user_orders(
+        &self,
+        owner: Address,
+        page_size: u64,
+    ) -> Result<impl Stream<Item = reqwest::Result<Order>> + '_, PageSizeError> {
+        paginate(page_size, move |offset, limit| async move {
+            self.get_user_orders(&owner, Some(offset), Some(limit)).await
+        })
+    }
+
     // Token endpoints
     pub async fn get_native_price(&self, token: &Address) -> reqwest::Result<NativePriceResponse> {
         let url = url::join(&self.base, &format!("api/v1/token/{:x}/native_price", token));
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v1/token/{token}/native_price", request)
             .await?
             .error_for_status()?
             .json()
@@ -220,12 +294,10 @@ impl OrderBookApi {
     }
 
     // Quote endpoints
-    pub async fn get_quote(&self, request: &OrderQuoteRequest) -> reqwest::Result<OrderQuoteResponse> {
+    pub async fn get_quote(&self, quote: &OrderQuoteRequest) -> reqwest::Result<OrderQuoteResponse> {
         let url = url::join(&self.base, "api/v1/quote");
-        self.client
-            .post(url)
-            .json(request)
-            .send()
+        let request = self.client.post(url).json(quote);
+        self.send("api/v1/quote", request)
             .await?
             .error_for_status()?
             .json()
@@ -235,9 +307,8 @@ impl OrderBookApi {
     // Solver competition endpoints (v2)
     pub async fn get_solver_competition_v2(&self, auction_id: u64) -> reqwest::Result<SolverCompetitionResponse> {
         let url = url::join(&self.base, &format!("api/v2/solver_competition/{auction_id}"));
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v2/solver_competition/{auction_id}", request)
             .await?
             .error_for_status()?
             .json()
@@ -246,9 +317,8 @@ impl OrderBookApi {
 
     pub async fn get_solver_competition_by_tx_v2(&self, tx_hash: &H256) -> reqwest::Result<SolverCompetitionResponse> {
         let url = url::join(&self.base, &format!("api/v2/solver_competition/by_tx_hash/{:x}", tx_hash));
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v2/solver_competition/by_tx_hash/{tx_hash}", request)
             .await?
             .error_for_status()?
             .json()
@@ -257,9 +327,8 @@ impl OrderBookApi {
 
     pub async fn get_latest_solver_competition_v2(&self) -> reqwest::Result<SolverCompetitionResponse> {
         let url = url::join(&self.base, "api/v2/solver_competition/latest");
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v2/solver_competition/latest", request)
             .await?
             .error_for_status()?
             .json()
@@ -269,9 +338,8 @@ impl OrderBookApi {
     // Version endpoint
     pub async fn get_version(&self) -> reqwest::Result<String> {
         let url = url::join(&self.base, "api/v1/version");
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v1/version", request)
             .await?
             .error_for_status()?
             .text()
@@ -281,9 +349,8 @@ impl OrderBookApi {
     // App data endpoints
     pub async fn get_app_data(&self, app_data_hash: &AppDataHash) -> reqwest::Result<AppDataDocument> {
         let url = url::join(&self.base, &format!("api/v1/app_data/{}", serde_json::to_string(app_data_hash).unwrap()));
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v1/app_data/{app_data_hash}", request)
             .await?
             .error_for_status()?
             .json()
@@ -292,10 +359,8 @@ impl OrderBookApi {
 
     pub async fn register_app_data(&self, app_data_hash: &AppDataHash, app_data: &AppDataDocument) -> reqwest::Result<AppDataHash> {
         let url = url::join(&self.base, &format!("api/v1/app_data/{}", serde_json::to_string(app_data_hash).unwrap()));
-        self.client
-            .put(url)
-            .json(app_data)
-            .send()
+        let request = self.client.put(url).json(app_data);
+        self.send("api/v1/app_data/{app_data_hash}", request)
             .await?
             .error_for_status()?
             .json()
@@ -304,10 +369,8 @@ impl OrderBookApi {
 
     pub async fn register_app_data_auto(&self, app_data: &AppDataDocument) -> reqwest::Result<AppDataHash> {
         let url = url::join(&self.base, "api/v1/app_data");
-        self.client
-            .put(url)
-            .json(app_data)
-            .send()
+        let request = self.client.put(url).json(app_data);
+        self.send("api/v1/app_data", request)
             .await?
             .error_for_status()?
             .json()
@@ -317,9 +380,8 @@ impl OrderBookApi {
     // User endpoints
     pub async fn get_user_total_surplus(&self, address: &Address) -> reqwest::Result<TotalSurplus> {
         let url = url::join(&self.base, &format!("api/v1/users/{:x}/total_surplus", address));
-        self.client
-            .get(url)
-            .send()
+        let request = self.client.get(url);
+        self.send("api/v1/users/{address}/total_surplus", request)
             .await?
             .error_for_status()?
             .json()
@@ -327,3 +389,430 @@ impl OrderBookApi {
     }
 }
 
+/// Flattens the pages returned by `fetch(offset, limit)` into a stream of
+/// items, requesting the next page once the current one is consumed.
+fn paginate<T, F, Fut>(
+    page_size: u64,
+    fetch: F,
+) -> Result<impl Stream<Item = reqwest::Result<T>>, PageSizeError>
+where
+    F: FnMut(u64, u64) -> Fut,
+    Fut: Future<Output = reqwest::Result<Vec<T>>>,
+{
+    match page_size {
+        0 => return Err(PageSizeError::Zero),
+        size if size > MAX_PAGE_SIZE => return Err(PageSizeError::TooLarge(size)),
+        _ => (),
+    }
+    Ok(stream::try_unfold((fetch, Some(0)), move |(mut fetch, offset)| async move {
+        let Some(offset) = offset else {
+            return Ok(None);
+        };
+        let page = fetch(offset, page_size).await?;
+        let next = (page.len() as u64 == page_size).then_some(offset + page_size);
+        Ok::<_, reqwest::Error>(Some((stream::iter(page.into_iter().map(Ok)), (fetch, next))))
+    })
+    .try_flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::testing,
+        axum::{
+            Json,
+            Router,
+            extract::Query,
+            http::{StatusCode, header::RETRY_AFTER},
+            response::IntoResponse,
+            routing::{get, post},
+        },
+        futures::StreamExt,
+        model::signature::{EcdsaSignature, EcdsaSigningScheme},
+        primitive_types::H160,
+        serde::Serialize,
+        std::{
+            sync::{
+                Arc,
+                Mutex,
+                atomic::{AtomicUsize, Ordering},
+            },
+            time::{Duration, Instant},
+        },
+    };
+
+    async fn local_api(router: Router) -> OrderBookApi {
+        OrderBookApi::new(Client::new(), &testing::serve(router).await).unwrap()
+    }
+
+    #[test]
+    fn rejects_invalid_base_url() {
+        assert!(matches!(
+            OrderBookApi::new(Client::new(), "api.cow.fi/mainnet"),
+            Err(ConfigError::InvalidUrl { .. })
+        ));
+    }
+
+    fn order() -> OrderCreation {
+        OrderCreation {
+            sell_token: H160([0xaa; 20]),
+            buy_token: H160([0xbb; 20]),
+            sell_amount: 1_000.into(),
+            buy_amount: 990.into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn creates_order_with_body() {
+        let received = Arc::new(Mutex::new(None));
+        let router = Router::new().route(
+            "/api/v1/orders",
+            post({
+                let received = received.clone();
+                move |Json(body): Json<serde_json::Value>| async move {
+                    *received.lock().unwrap() = Some(body);
+                    (StatusCode::CREATED, Json(OrderUid([0x11; 56])))
+                }
+            }),
+        );
+        let api = local_api(router).await;
+
+        let uid = api.create_order(&order()).await.unwrap();
+
+        assert_eq!(uid, OrderUid([0x11; 56]));
+        let body = received.lock().unwrap().take().unwrap();
+        assert_eq!(body, serde_json::to_value(order()).unwrap());
+        assert_eq!(body["sellAmount"], "1000");
+    }
+
+    #[tokio::test]
+    async fn returns_typed_rejections() {
+        let router = Router::new().route(
+            "/api/v1/orders",
+            post(|| async {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "errorType": "DuplicatedOrder",
+                        "description": "order already exists",
+                    })),
+                )
+            }),
+        );
+        let api = local_api(router).await;
+
+        let result = api.create_order(&order()).await;
+
+        assert!(matches!(
+            result,
+            Err(OrderbookError::DuplicatedOrder(description)) if description == "order already exists"
+        ));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_order_creation() {
+        let (router, requests) = testing::flaky(
+            "/api/v1/orders",
+            vec![StatusCode::INTERNAL_SERVER_ERROR.into_response()],
+            serde_json::to_value(OrderUid([0x11; 56])).unwrap(),
+        );
+        let api = local_api(router).await.with_policy(fast_retries());
+
+        let result = api.create_order(&order()).await;
+
+        assert!(matches!(
+            result,
+            Err(OrderbookError::Http { status, .. }) if status == StatusCode::INTERNAL_SERVER_ERROR
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_order_cancellation() {
+        let uid = OrderUid([0x11; 56]);
+        let (router, requests) = testing::flaky(
+            "/api/v1/orders/{uid}",
+            vec![StatusCode::INTERNAL_SERVER_ERROR.into_response()],
+            serde_json::Value::Null,
+        );
+        let api = local_api(router).await.with_policy(fast_retries());
+
+        let cancellation = CancellationPayload {
+            signature: EcdsaSignature::default(),
+            signing_scheme: EcdsaSigningScheme::Eip712,
+        };
+        let err = api.cancel_order(&uid, &cancellation).await.unwrap_err();
+
+        assert_eq!(err.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn returns_unstructured_failures() {
+        let router = Router::new().route(
+            "/api/v1/orders",
+            post(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "boom") }),
+        );
+        let api = local_api(router).await.with_policy(RequestPolicy::no_retries());
+
+        let result = api.create_order(&order()).await;
+
+        assert!(matches!(
+            result,
+            Err(OrderbookError::Http { status, body })
+                if status == StatusCode::INTERNAL_SERVER_ERROR && body == "boom"
+        ));
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Page {
+        offset: usize,
+        limit: usize,
+    }
+
+    /// Serves `total` items built by `item` in pages and counts the requests.
+    fn paged<T: Serialize + Send + 'static>(
+        path: &str,
+        total: usize,
+        item: fn(usize) -> T,
+    ) -> (Router, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let router = Router::new().route(
+            path,
+            get({
+                let requests = requests.clone();
+                move |Query(page): Query<Page>| async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    let end = total.min(page.offset + page.limit);
+                    Json((page.offset.min(end)..end).map(item).collect::<Vec<_>>())
+                }
+            }),
+        );
+        (router, requests)
+    }
+
+    #[tokio::test]
+    async fn streams_user_orders_page_by_page() {
+        let (router, requests) = paged("/api/v1/account/{owner}/orders", 5, |index| {
+            let mut order: serde_json::Value =
+                serde_json::from_str(include_str!("../testdata/order_limit.json")).unwrap();
+            order["validTo"] = index.into();
+            order
+        });
+        let api = local_api(router).await;
+        let mut orders = Box::pin(api.stream_user_orders(H160([0x22; 20]), 2).unwrap());
+
+        // Only the pages that were consumed are requested.
+        orders.next().await.unwrap().unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let rest = orders
+            .map(|order| order.unwrap().valid_to)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(rest, vec![1, 2, 3, 4]);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn streams_trades_until_short_page() {
+        let (router, requests) = paged("/api/v2/trades", 4, |index| Trade {
+            log_index: index as u64,
+            ..Default::default()
+        });
+        let api = local_api(router).await;
+
+        let trades: Vec<Trade> = api
+            .stream_trades_by_owner(H160([0x22; 20]), 2)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            trades.iter().map(|trade| trade.log_index).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        // The last page is full, so an empty one ends the stream.
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn stops_streaming_after_error() {
+        let router = Router::new().route(
+            "/api/v2/trades",
+            get(|| async { StatusCode::SERVICE_UNAVAILABLE }),
+        );
+        let api = local_api(router).await.with_policy(RequestPolicy::no_retries());
+
+        let results = api
+            .stream_trades_by_owner(H160([0x22; 20]), 2)
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_pages() {
+        let (router, requests) = paged("/api/v2/trades", 4, |index| Trade {
+            log_index: index as u64,
+            ..Default::default()
+        });
+        let api = local_api(router).await;
+
+        assert!(matches!(
+            api.stream_trades_by_owner(H160([0x22; 20]), 0),
+            Err(PageSizeError::Zero)
+        ));
+        assert!(matches!(
+            api.stream_user_orders(H160([0x22; 20]), 0),
+            Err(PageSizeError::Zero)
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn rejects_pages_above_orderbook_limit() {
+        let (router, requests) = paged("/api/v2/trades", 4, |index| Trade {
+            log_index: index as u64,
+            ..Default::default()
+        });
+        let api = local_api(router).await;
+
+        assert!(matches!(
+            api.stream_trades_by_owner(H160([0x22; 20]), MAX_PAGE_SIZE + 1),
+            Err(PageSizeError::TooLarge(size)) if size == MAX_PAGE_SIZE + 1
+        ));
+        assert!(matches!(
+            api.stream_user_orders(H160([0x22; 20]), u64::MAX),
+            Err(PageSizeError::TooLarge(u64::MAX))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        // The limit itself is fine.
+        let trades: Vec<Trade> = api
+            .stream_trades_by_owner(H160([0x22; 20]), MAX_PAGE_SIZE)
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(trades.len(), 4);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    fn fast_retries() -> RequestPolicy {
+        RequestPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (router, requests) = testing::flaky(
+            "/api/v1/version",
+            vec![
+                StatusCode::SERVICE_UNAVAILABLE.into_response(),
+                StatusCode::BAD_GATEWAY.into_response(),
+            ],
+            "v1.2.3".into(),
+        );
+        let api = local_api(router).await.with_policy(fast_retries());
+
+        let version = api.get_version().await.unwrap();
+
+        assert_eq!(version, r#""v1.2.3""#);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let failures = (0..3)
+            .map(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            .collect();
+        let (router, requests) = testing::flaky("/api/v1/version", failures, "v1.2.3".into());
+        let api = local_api(router).await.with_policy(RequestPolicy {
+            max_retries: 2,
+            ..fast_retries()
+        });
+
+        let err = api.get_version().await.unwrap_err();
+
+        assert_eq!(err.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (router, requests) = testing::flaky(
+            "/api/v1/version",
+            vec![StatusCode::NOT_FOUND.into_response()],
+            "v1.2.3".into(),
+        );
+        let api = local_api(router).await.with_policy(fast_retries());
+
+        let err = api.get_version().await.unwrap_err();
+
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn honours_retry_after() {
+        let (router, requests) = testing::flaky(
+            "/api/v1/version",
+            vec![(StatusCode::TOO_MANY_REQUESTS, [(RETRY_AFTER, "1")]).into_response()],
+            "v1.2.3".into(),
+        );
+        let api = local_api(router).await.with_policy(fast_retries());
+
+        let start = Instant::now();
+        api.get_version().await.unwrap();
+
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn caps_retry_after_at_max_backoff() {
+        let (router, requests) = testing::flaky(
+            "/api/v1/version",
+            vec![(StatusCode::SERVICE_UNAVAILABLE, [(RETRY_AFTER, "3600")]).into_response()],
+            "v1.2.3".into(),
+        );
+        let api = local_api(router).await.with_policy(RequestPolicy {
+            max_backoff: Duration::from_millis(10),
+            ..fast_retries()
+        });
+
+        let start = Instant::now();
+        api.get_version().await.unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn times_out_per_endpoint() {
+        let router = Router::new().route(
+            "/api/v1/version",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                "v1.2.3"
+            }),
+        );
+        let api = local_api(router).await.with_policy(
+            RequestPolicy::no_retries()
+                .with_endpoint_timeout("api/v1/version", Duration::from_millis(50)),
+        );
+
+        let err = api.get_version().await.unwrap_err();
+
+        assert!(err.is_timeout());
+    }
+}
@@ -0,0 +1,206 @@
+//! Chains and environments of the orderbook API.
+//!
+//! A [`Config`] names the chain and the orderbook environment to talk to. It
+//! resolves the base URL of the API and the chain id under which contract
+//! deployments are registered, so the API client and the contract bindings
+//! are always pointed at the same network.
+
+use {
+    crate::errors::ConfigError,
+    ethcontract::{Address, Contract},
+    url::Url,
+};
+
+/// Chains that the orderbook is deployed on.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Chain {
+    Mainnet,
+    Gnosis,
+    ArbitrumOne,
+    Base,
+    Avalanche,
+    Polygon,
+    Sepolia,
+}
+
+impl Chain {
+    pub const ALL: [Self; 7] = [
+        Self::Mainnet,
+        Self::Gnosis,
+        Self::ArbitrumOne,
+        Self::Base,
+        Self::Avalanche,
+        Self::Polygon,
+        Self::Sepolia,
+    ];
+
+    /// Chain id, which is also the network id of the contract deployments.
+    pub fn id(self) -> u64 {
+        match self {
+            Self::Mainnet => 1,
+            Self::Gnosis => 100,
+            Self::ArbitrumOne => 42161,
+            Self::Base => 8453,
+            Self::Avalanche => 43114,
+            Self::Polygon => 137,
+            Self::Sepolia => 11155111,
+        }
+    }
+
+    /// Name of the chain in orderbook URLs.
+    pub fn path(self) -> &'static str {
+        match self {
+            Self::Mainnet => "mainnet",
+            Self::Gnosis => "xdai",
+            Self::ArbitrumOne => "arbitrum_one",
+            Self::Base => "base",
+            Self::Avalanche => "avalanche",
+            Self::Polygon => "polygon",
+            Self::Sepolia => "sepolia",
+        }
+    }
+}
+
+impl TryFrom<u64> for Chain {
+    type Error = ConfigError;
+
+    fn try_from(chain_id: u64) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|chain| chain.id() == chain_id)
+            .ok_or(ConfigError::UnsupportedChain(chain_id))
+    }
+}
+
+/// Orderbook environment.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Env {
+    /// `https://api.cow.fi`.
+    Prod,
+    /// `https://barn.api.cow.fi`, which settles with the same contracts as
+    /// prod.
+    Staging,
+    /// Orderbook at the given base URL, e.g. a local one. The URL already
+    /// includes the chain.
+    Custom(String),
+}
+
+/// Chain and environment of the orderbook.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    pub chain: Chain,
+    pub env: Env,
+}
+
+impl Config {
+    pub fn new(chain: Chain, env: Env) -> Self {
+        Self { chain, env }
+    }
+
+    /// Config for the chain with `chain_id`, e.g. as reported by the node.
+    pub fn for_chain_id(chain_id: u64, env: Env) -> Result<Self, ConfigError> {
+        Ok(Self::new(Chain::try_from(chain_id)?, env))
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain.id()
+    }
+
+    /// Base URL of the orderbook API.
+    pub fn base_url(&self) -> Result<Url, ConfigError> {
+        match &self.env {
+            Env::Prod => parse_base_url(&format!("https://api.cow.fi/{}", self.chain.path())),
+            Env::Staging => {
+                parse_base_url(&format!("https://barn.api.cow.fi/{}", self.chain.path()))
+            }
+            Env::Custom(url) => parse_base_url(url),
+        }
+    }
+
+    /// Address of `contract` on the configured chain.
+    pub fn address(&self, contract: &Contract) -> anyhow::Result<Address> {
+        Ok(contracts::deployment(contract, self.chain_id())?.address)
+    }
+}
+
+/// Parses the base URL of an orderbook, which has to be an HTTP(S) URL with a
+/// host.
+pub fn parse_base_url(url: &str) -> Result<Url, ConfigError> {
+    let invalid = |reason: String| ConfigError::InvalidUrl {
+        url: url.to_owned(),
+        reason,
+    };
+    let parsed = Url::parse(url).map_err(|err| invalid(err.to_string()))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(invalid(format!("unsupported scheme {}", parsed.scheme())));
+    }
+    if parsed.host().is_none() {
+        return Err(invalid("missing host".to_owned()));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, contracts::WETH9};
+
+    #[test]
+    fn resolves_base_urls() {
+        let url = |chain, env| Config::new(chain, env).base_url().unwrap().to_string();
+        assert_eq!(url(Chain::Mainnet, Env::Prod), crate::urls::MAINNET_PROD);
+        assert_eq!(
+            url(Chain::Gnosis, Env::Staging),
+            crate::urls::GNOSIS_STAGING
+        );
+        assert_eq!(
+            url(Chain::ArbitrumOne, Env::Prod),
+            crate::urls::ARBITRUM_ONE_PROD
+        );
+        assert_eq!(
+            url(
+                Chain::Sepolia,
+                Env::Custom("http://localhost:8080/".to_owned())
+            ),
+            "http://localhost:8080/"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_base_urls() {
+        for url in ["api.cow.fi/mainnet", "ftp://api.cow.fi/mainnet", "http://"] {
+            let config = Config::new(Chain::Mainnet, Env::Custom(url.to_owned()));
+            assert!(matches!(
+                config.base_url(),
+                Err(ConfigError::InvalidUrl { url: invalid, .. }) if invalid == url
+            ));
+        }
+    }
+
+    #[test]
+    fn maps_chain_ids() {
+        for chain in Chain::ALL {
+            assert_eq!(Chain::try_from(chain.id()).unwrap(), chain);
+        }
+        assert!(matches!(
+            Config::for_chain_id(5, Env::Prod),
+            Err(ConfigError::UnsupportedChain(5))
+        ));
+    }
+
+    #[test]
+    fn resolves_contract_addresses() {
+        let weth = |chain| Config::new(chain, Env::Prod).address(WETH9::raw_contract());
+        assert_eq!(
+            weth(Chain::Mainnet).unwrap(),
+            "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"
+                .parse::<Address>()
+                .unwrap()
+        );
+        assert_eq!(
+            weth(Chain::Gnosis).unwrap(),
+            "0xe91D153E0b41518A2Ce8Dd3D7944Fa863463a97d"
+                .parse::<Address>()
+                .unwrap()
+        );
+    }
+}
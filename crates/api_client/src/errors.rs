@@ -0,0 +1,179 @@
+use {
+    reqwest::{Response, StatusCode},
+    serde::Deserialize,
+};
+
+/// Reasons why the orderbook did not accept a request.
+///
+/// Rejections the orderbook reports with an `errorType` get their own variant
+/// if callers commonly react to them, the others end up in [`Self::Other`].
+/// The variants carry the orderbook's description of the problem.
+#[derive(Debug, thiserror::Error)]
+pub enum OrderbookError {
+    /// The fee of the order does not cover the expected execution costs.
+    #[error("InsufficientFee: {0}")]
+    InsufficientFee(String),
+    /// The signature does not match the order.
+    #[error("InvalidSignature: {0}")]
+    InvalidSignature(String),
+    /// The EIP-1271 signature was rejected by the signing contract.
+    #[error("InvalidEip1271Signature: {0}")]
+    InvalidEip1271Signature(String),
+    /// An order with the same uid exists already.
+    #[error("DuplicatedOrder: {0}")]
+    DuplicatedOrder(String),
+    /// The sell amount plus the fee overflows.
+    #[error("SellAmountOverflow: {0}")]
+    SellAmountOverflow(String),
+    /// The quote referenced by the order does not exist or expired.
+    #[error("QuoteNotFound: {0}")]
+    QuoteNotFound(String),
+    /// The owner does not have enough sell tokens.
+    #[error("InsufficientBalance: {0}")]
+    InsufficientBalance(String),
+    /// The owner did not approve the vault relayer for enough sell tokens.
+    #[error("InsufficientAllowance: {0}")]
+    InsufficientAllowance(String),
+    /// The order expires too soon.
+    #[error("InsufficientValidTo: {0}")]
+    InsufficientValidTo(String),
+    /// The order is valid for too long.
+    #[error("ExcessiveValidTo: {0}")]
+    ExcessiveValidTo(String),
+    /// The sell or buy amount is zero.
+    #[error("ZeroAmount: {0}")]
+    ZeroAmount(String),
+    /// The orderbook does not trade one of the tokens.
+    #[error("UnsupportedToken: {0}")]
+    UnsupportedToken(String),
+    /// The app data is malformed or does not match its hash.
+    #[error("InvalidAppData: {0}")]
+    InvalidAppData(String),
+    /// Any other rejection with an `errorType`.
+    #[error("{error_type}: {description}")]
+    Other {
+        error_type: String,
+        description: String,
+    },
+    /// The request failed with a response that is not an orderbook error.
+    #[error("HTTP {status}: {body}")]
+    Http { status: StatusCode, body: String },
+    /// The request could not be sent or its response not be read.
+    #[error(transparent)]
+    Transport(#[from] reqwest::Error),
+}
+
+/// Invalid orderbook configurations.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("invalid orderbook URL {url:?}: {reason}")]
+    InvalidUrl { url: String, reason: String },
+    #[error("chain {0} is not supported by the orderbook")]
+    UnsupportedChain(u64),
+}
+
+/// Page sizes the orderbook does not serve.
+#[derive(Debug, thiserror::Error)]
+pub enum PageSizeError {
+    /// Paginated requests have to fetch at least one item per page.
+    #[error("page size must be positive")]
+    Zero,
+    /// The orderbook rejects every page with more items than
+    /// [`MAX_PAGE_SIZE`](crate::client::MAX_PAGE_SIZE).
+    #[error("page size {0} exceeds the limit of {max}", max = crate::client::MAX_PAGE_SIZE)]
+    TooLarge(u64),
+}
+
+/// Body of the orderbook's error responses.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ErrorBody {
+    error_type: String,
+    #[serde(default)]
+    description: String,
+}
+
+impl OrderbookError {
+    /// Converts an unsuccessful response.
+    pub async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        match response.text().await {
+            Ok(body) => Self::from_body(status, body),
+            Err(err) => err.into(),
+        }
+    }
+
+    fn from_body(status: StatusCode, body: String) -> Self {
+        let Ok(ErrorBody {
+            error_type,
+            description,
+        }) = serde_json::from_str(&body)
+        else {
+            return Self::Http { status, body };
+        };
+        match error_type.as_str() {
+            "InsufficientFee" => Self::InsufficientFee(description),
+            "InvalidSignature" => Self::InvalidSignature(description),
+            "InvalidEip1271Signature" => Self::InvalidEip1271Signature(description),
+            "DuplicatedOrder" | "DuplicateOrder" => Self::DuplicatedOrder(description),
+            "SellAmountOverflow" => Self::SellAmountOverflow(description),
+            "QuoteNotFound" => Self::QuoteNotFound(description),
+            "InsufficientBalance" => Self::InsufficientBalance(description),
+            "InsufficientAllowance" => Self::InsufficientAllowance(description),
+            "InsufficientValidTo" => Self::InsufficientValidTo(description),
+            "ExcessiveValidTo" => Self::ExcessiveValidTo(description),
+            "ZeroAmount" => Self::ZeroAmount(description),
+            "UnsupportedToken" => Self::UnsupportedToken(description),
+            "InvalidAppData" | "AppDataHashMismatch" => Self::InvalidAppData(description),
+            _ => Self::Other {
+                error_type,
+                description,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejection(error_type: &str) -> OrderbookError {
+        OrderbookError::from_body(
+            StatusCode::BAD_REQUEST,
+            format!(r#"{{"errorType":"{error_type}","description":"details"}}"#),
+        )
+    }
+
+    #[test]
+    fn classifies_rejections() {
+        assert!(matches!(
+            rejection("InsufficientFee"),
+            OrderbookError::InsufficientFee(description) if description == "details"
+        ));
+        assert!(matches!(
+            rejection("DuplicatedOrder"),
+            OrderbookError::DuplicatedOrder(_)
+        ));
+        assert!(matches!(
+            rejection("AppDataHashMismatch"),
+            OrderbookError::InvalidAppData(_)
+        ));
+        assert!(matches!(
+            rejection("TooManyLimitOrders"),
+            OrderbookError::Other { error_type, .. } if error_type == "TooManyLimitOrders"
+        ));
+    }
+
+    #[test]
+    fn keeps_unstructured_bodies() {
+        let err = OrderbookError::from_body(StatusCode::BAD_GATEWAY, "upstream down".to_owned());
+        assert!(matches!(
+            err,
+            OrderbookError::Http { status: StatusCode::BAD_GATEWAY, body } if body == "upstream down"
+        ));
+        assert_eq!(
+            rejection("QuoteNotFound").to_string(),
+            "QuoteNotFound: details"
+        );
+    }
+}
@@ -1,3 +1,9 @@
 pub mod client;
+pub mod config;
+pub mod errors;
 pub mod urls;
 pub mod models;
+pub mod policy;
+
+#[cfg(test)]
+mod testing;
@@ -1,23 +1,21 @@
 use {
     derive_more::Debug as DeriveDebug,
-    ethcontract::{
-        transaction::TransactionBuilder,
-        Address,
-        Bytes,
+    ethcontract::Address,
+    app_data::AppDataHash,
+    chrono::{DateTime, Utc},
+    model::{
+        interaction::InteractionData,
+        order::{
+            BuyTokenDestination, OrderClass, OrderKind, OrderStatus, OrderUid, SellTokenSource,
+        },
+        signature::SigningScheme,
     },
-    hex_literal::hex,
-    model::order::OrderUid,
     num::BigUint,
     number::serialization::HexOrDecimalU256,
     primitive_types::{H160, H256, U256},
-    serde::{de, Deserialize, Deserializer, Serialize, Serializer},
+    serde::{Deserialize, Serialize},
     serde_with::{serde_as, DisplayFromStr},
-    std::{
-        collections::{HashMap, HashSet},
-        fmt::{self, Debug, Display},
-        str::FromStr,
-    },
-    web3::Transport,
+    std::{collections::HashMap, fmt::Debug},
 };
 
 #[serde_as]
@@ -115,3 +113,168 @@ pub struct SolverCompetitionResponse {
     pub auction: CompetitionAuction,
     pub solutions: Vec<SolverSettlement>,
 }
+
+/// Order as returned by the orderbook's order endpoints, e.g.
+/// `api/v1/orders/{uid}` and `api/v1/account/{owner}/orders`.
+#[serde_as]
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Order {
+    pub creation_date: DateTime<Utc>,
+    pub owner: H160,
+    pub uid: OrderUid,
+    /// Sell token balance of the owner that is available for the order, if
+    /// the orderbook checked it.
+    #[serde_as(as = "Option<HexOrDecimalU256>")]
+    #[serde(default)]
+    pub available_balance: Option<U256>,
+    #[serde_as(as = "HexOrDecimalU256")]
+    pub executed_buy_amount: U256,
+    /// Executed sell amount including fees.
+    #[serde_as(as = "HexOrDecimalU256")]
+    pub executed_sell_amount: U256,
+    #[serde_as(as = "HexOrDecimalU256")]
+    pub executed_sell_amount_before_fees: U256,
+    #[serde_as(as = "HexOrDecimalU256")]
+    pub executed_fee_amount: U256,
+    /// Fee taken by the protocol and solvers, in `executed_fee_token`.
+    #[serde_as(as = "HexOrDecimalU256")]
+    #[serde(default)]
+    pub executed_fee: U256,
+    #[serde(default)]
+    pub executed_fee_token: H160,
+    pub invalidated: bool,
+    pub status: OrderStatus,
+    #[serde(flatten)]
+    pub class: OrderClass,
+    pub settlement_contract: H160,
+    #[serde(default)]
+    pub is_liquidity_order: bool,
+    /// App data document whose hash is `app_data`, if the orderbook knows it.
+    #[serde(default)]
+    pub full_app_data: Option<String>,
+    pub sell_token: H160,
+    pub buy_token: H160,
+    /// `None` if the order pays out to its owner.
+    pub receiver: Option<H160>,
+    #[serde_as(as = "HexOrDecimalU256")]
+    pub sell_amount: U256,
+    #[serde_as(as = "HexOrDecimalU256")]
+    pub buy_amount: U256,
+    pub valid_to: u32,
+    pub app_data: AppDataHash,
+    #[serde_as(as = "HexOrDecimalU256")]
+    pub fee_amount: U256,
+    pub kind: OrderKind,
+    pub partially_fillable: bool,
+    pub sell_token_balance: SellTokenSource,
+    pub buy_token_balance: BuyTokenDestination,
+    pub signing_scheme: SigningScheme,
+    pub signature: web3::types::Bytes,
+    #[serde(default)]
+    pub interactions: OrderInteractions,
+    /// Set for orders placed through the ETH flow contract.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ethflow_data: Option<EthflowData>,
+    /// Account that placed an on-chain order, which is owned by a contract.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub onchain_user: Option<H160>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub onchain_order_data: Option<OnchainOrderData>,
+}
+
+impl Order {
+    pub fn is_liquidity_order(&self) -> bool {
+        matches!(self.class, OrderClass::Liquidity)
+    }
+
+    /// Amount of the sell token for sell orders and of the buy token for buy
+    /// orders that was executed so far, excluding fees.
+    pub fn executed_amount(&self) -> U256 {
+        match self.kind {
+            OrderKind::Sell => self.executed_sell_amount_before_fees,
+            OrderKind::Buy => self.executed_buy_amount,
+        }
+    }
+
+    /// Whether the order was executed in full. Orders that are not partially
+    /// fillable are either executed in full or not at all.
+    pub fn is_fully_executed(&self) -> bool {
+        let amount = match self.kind {
+            OrderKind::Sell => self.sell_amount,
+            OrderKind::Buy => self.buy_amount,
+        };
+        self.executed_amount() >= amount
+    }
+}
+
+/// Hooks the settlement executes around an order.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct OrderInteractions {
+    pub pre: Vec<InteractionData>,
+    pub post: Vec<InteractionData>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EthflowData {
+    /// Transaction that refunded the ETH of the expired order, if any.
+    pub refund_tx_hash: Option<H256>,
+    /// Expiry chosen by the user. The order itself is valid forever.
+    pub user_valid_to: i64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnchainOrderData {
+    pub sender: H160,
+    /// Reason the orderbook could not place the order, if any.
+    pub placement_error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT_ORDER: &str = include_str!("../testdata/order_limit.json");
+    const ETHFLOW_ORDER: &str = include_str!("../testdata/order_ethflow.json");
+
+    fn round_trip(fixture: &str) -> Order {
+        let json: serde_json::Value = serde_json::from_str(fixture).unwrap();
+        let order: Order = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&order).unwrap(), json);
+        order
+    }
+
+    #[test]
+    fn round_trips_limit_order() {
+        let order = round_trip(LIMIT_ORDER);
+
+        assert_eq!(order.status, OrderStatus::Open);
+        assert_eq!(order.class, OrderClass::Limit);
+        assert_eq!(order.signing_scheme, SigningScheme::Eip712);
+        assert_eq!(order.signature.0.len(), 65);
+        assert_eq!(order.interactions.pre.len(), 1);
+        assert_eq!(order.receiver, None);
+        assert!(order.partially_fillable);
+        assert_eq!(order.executed_amount(), U256::exp10(18) / 2);
+        assert!(!order.is_fully_executed());
+        assert!(order.ethflow_data.is_none());
+    }
+
+    #[test]
+    fn round_trips_ethflow_order() {
+        let order = round_trip(ETHFLOW_ORDER);
+
+        assert_eq!(order.status, OrderStatus::Fulfilled);
+        assert_eq!(order.class, OrderClass::Market);
+        assert_eq!(order.signing_scheme, SigningScheme::Eip1271);
+        assert_eq!(order.kind, OrderKind::Buy);
+        assert!(order.is_fully_executed());
+        assert_eq!(order.ethflow_data.unwrap().refund_tx_hash, None);
+        assert_eq!(
+            order.onchain_order_data.unwrap().sender,
+            order.onchain_user.unwrap()
+        );
+    }
+}
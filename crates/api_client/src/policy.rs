@@ -0,0 +1,232 @@
+//! Retries, timeouts and rate limiting of orderbook requests.
+
+use {
+    reqwest::{Response, StatusCode, header::RETRY_AFTER},
+    std::{
+        collections::HashMap,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+/// How [`OrderBookApi`](crate::client::OrderBookApi) sends its requests.
+///
+/// Endpoints are identified by their path template, e.g.
+/// `api/v1/orders/{uid}`.
+#[derive(Clone, Debug)]
+pub struct RequestPolicy {
+    /// How often a request is retried after it failed with a connection
+    /// error, a timeout, `429 Too Many Requests` or a server error. Only
+    /// requests that are safe to repeat are retried: order creation and
+    /// cancellation are sent once, since the orderbook may have processed a
+    /// failed attempt already.
+    pub max_retries: u32,
+    /// Delay before the first retry, which doubles with every further one.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between retries, which also caps the
+    /// `Retry-After` of the orderbook.
+    pub max_backoff: Duration,
+    /// Timeout of a single attempt.
+    pub timeout: Duration,
+    /// Timeouts that replace `timeout` for specific endpoints.
+    pub endpoint_timeouts: HashMap<&'static str, Duration>,
+    /// Limits the rate of requests across all endpoints.
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            endpoint_timeouts: HashMap::new(),
+            rate_limit: None,
+        }
+    }
+}
+
+impl RequestPolicy {
+    /// Policy that sends every request exactly once.
+    pub fn no_retries() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_endpoint_timeout(mut self, endpoint: &'static str, timeout: Duration) -> Self {
+        self.endpoint_timeouts.insert(endpoint, timeout);
+        self
+    }
+
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
+    /// Timeout of a single attempt to reach `endpoint`.
+    pub fn timeout(&self, endpoint: &str) -> Duration {
+        self.endpoint_timeouts
+            .get(endpoint)
+            .copied()
+            .unwrap_or(self.timeout)
+    }
+
+    /// Delay before the retry that follows `attempt` failed attempts.
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+
+    /// Delay before retrying a request whose `attempt`-th retry (counting the
+    /// initial request as zero) resulted in `outcome`, or `None` if the
+    /// outcome is final.
+    pub(crate) fn retry_delay(
+        &self,
+        attempt: u32,
+        outcome: &reqwest::Result<Response>,
+    ) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        match outcome {
+            Ok(response) => {
+                let status = response.status();
+                if status != StatusCode::TOO_MANY_REQUESTS && !status.is_server_error() {
+                    return None;
+                }
+                let delay = match retry_after(response) {
+                    Some(delay) => delay.min(self.max_backoff),
+                    None => self.backoff(attempt),
+                };
+                Some(delay)
+            }
+            Err(err) if err.is_connect() || err.is_timeout() => Some(self.backoff(attempt)),
+            Err(_) => None,
+        }
+    }
+}
+
+/// `Retry-After` of a response in seconds. HTTP dates are not supported.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Token bucket that allows bursts of `capacity` requests and refills one
+/// token every `interval`.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub interval: Duration,
+}
+
+/// Tokens left of a [`RateLimit`].
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative if requests wait for tokens.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: limit.capacity.into(),
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token, waiting until one is available. Waiting requests are
+    /// served in the order they arrived.
+    pub(crate) async fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refilled = (now - bucket.updated).as_secs_f64() / self.limit.interval.as_secs_f64();
+            bucket.tokens = (bucket.tokens + refilled).min(self.limit.capacity.into()) - 1.;
+            bucket.updated = now;
+            if bucket.tokens >= 0. {
+                return;
+            }
+            self.limit.interval.mul_f64(-bucket.tokens)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, tokio::net::TcpListener};
+
+    #[test]
+    fn doubles_backoff_up_to_limit() {
+        let policy = RequestPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            ..Default::default()
+        };
+        let backoffs = (0..5).map(|attempt| policy.backoff(attempt).as_millis());
+        assert_eq!(backoffs.collect::<Vec<_>>(), vec![100, 200, 400, 500, 500]);
+    }
+
+    #[test]
+    fn overrides_timeouts_per_endpoint() {
+        let policy =
+            RequestPolicy::default().with_endpoint_timeout("api/v1/quote", Duration::from_secs(2));
+        assert_eq!(policy.timeout("api/v1/quote"), Duration::from_secs(2));
+        assert_eq!(policy.timeout("api/v1/auction"), policy.timeout);
+    }
+
+    #[tokio::test]
+    async fn retries_connection_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let outcome = reqwest::get(format!("http://{address}")).await;
+
+        let policy = RequestPolicy::default();
+        assert_eq!(
+            policy.retry_delay(0, &outcome),
+            Some(policy.initial_backoff)
+        );
+        assert_eq!(policy.retry_delay(policy.max_retries, &outcome), None);
+    }
+
+    #[tokio::test]
+    async fn limits_request_rate() {
+        let limiter = RateLimiter::new(RateLimit {
+            capacity: 2,
+            interval: Duration::from_millis(50),
+        });
+
+        let start = Instant::now();
+        for _ in 0..2 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+        for _ in 0..2 {
+            limiter.acquire().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
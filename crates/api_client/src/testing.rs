@@ -0,0 +1,48 @@
+//! Local HTTP server that stands in for the orderbook in tests.
+
+use {
+    axum::{
+        Json,
+        Router,
+        response::{IntoResponse, Response},
+        routing::any,
+    },
+    std::sync::{
+        Arc,
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    tokio::net::TcpListener,
+};
+
+/// Serves `router` on a free local port and returns its base URL.
+pub async fn serve(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{address}")
+}
+
+/// Router that answers the first requests to `path` with `failures`, one per
+/// request, and all further ones with `body`. Also returns the number of
+/// requests it received.
+pub fn flaky(
+    path: &str,
+    failures: Vec<Response>,
+    body: serde_json::Value,
+) -> (Router, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let failures = Arc::new(Mutex::new(failures.into_iter()));
+    let router = Router::new().route(
+        path,
+        any({
+            let requests = requests.clone();
+            move || async move {
+                requests.fetch_add(1, Ordering::SeqCst);
+                let failure = failures.lock().unwrap().next();
+                failure.unwrap_or_else(|| Json(body).into_response())
+            }
+        }),
+    );
+    (router, requests)
+}
@@ -1,3 +1,6 @@
+//! Base URLs of the orderbook per chain and environment. [`crate::config`]
+//! resolves and validates them together with the chain id.
+
 pub const MAINNET_PROD: &str = "https://api.cow.fi/mainnet";
 pub const MAINNET_STAGING: &str = "https://barn.api.cow.fi/mainnet";
 
@@ -1,10 +1,10 @@
 use {
     ethcontract::{
         Address,
-        common::{DeploymentInformation, contract::Network},
+        common::{DeploymentInformation, abi::ParamType, contract::Network},
     },
     ethcontract_generate::{ContractBuilder, loaders::TruffleLoader},
-    std::{env, path::Path},
+    std::{collections::BTreeMap, env, fmt::Write, fs, path::Path},
 };
 
 #[path = "src/paths.rs"]
@@ -391,6 +391,7 @@ fn main() {
             )
     });
     generate_contract("BCowPool");
+    generate_cow_amm_reverts(&["BCowHelper", "BCowPool"]);
            
     // Support contracts used for trade and token simulations.
     // generate_contract("Solver");
@@ -518,6 +519,65 @@ fn generate_contract_with_config(
         .unwrap();
 }
 
+/// Writes the `cow_amm_reverts!` invocation that defines `CowAmmRevert` with
+/// a variant for every custom error declared in the ABIs of `contracts`.
+fn generate_cow_amm_reverts(contracts: &[&str]) {
+    let mut errors = BTreeMap::new();
+    for name in contracts {
+        let path = paths::contract_artifacts_dir()
+            .join(name)
+            .with_extension("json");
+        let contract = TruffleLoader::new()
+            .name(*name)
+            .load_contract_from_file(&path)
+            .unwrap();
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        for overloads in contract.interface.abi.errors.values() {
+            // Variants are named after the errors, so they can't be
+            // overloaded.
+            let [error] = overloads.as_slice() else {
+                panic!("overloaded error {} in {name}", overloads[0].name);
+            };
+            if let Some(known) = errors.insert(error.name.clone(), error.clone()) {
+                assert_eq!(
+                    known.signature(),
+                    error.signature(),
+                    "conflicting definitions of error {}",
+                    error.name
+                );
+            }
+        }
+    }
+
+    let mut code = String::from("cow_amm_reverts! {\n");
+    for (name, error) in errors {
+        let fields = error
+            .inputs
+            .iter()
+            .map(|input| {
+                let ty = match input.kind {
+                    ParamType::Address => "Address",
+                    ParamType::Uint(256) => "U256",
+                    ref kind => panic!("unsupported parameter {kind:?} of error {name}"),
+                };
+                format!("{}: {ty}", input.name)
+            })
+            .collect::<Vec<_>>();
+        let fields = if fields.is_empty() {
+            String::new()
+        } else {
+            format!(" {{ {} }}", fields.join(", "))
+        };
+        let selector = u32::from_be_bytes(error.signature()[..4].try_into().unwrap());
+        writeln!(code, "    {name}{fields} = 0x{selector:08x};").unwrap();
+    }
+    code.push_str("}\n");
+
+    let dest = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&dest).join("cow_amm_reverts.rs"), code).unwrap();
+}
+
 fn addr(s: &str) -> Address {
     s.parse().unwrap()
 }
\ No newline at end of file
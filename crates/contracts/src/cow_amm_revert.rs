@@ -0,0 +1,188 @@
+//! Decoding of the custom errors the CoW AMM contracts (`BCowPool` and
+//! `BCowHelper`) revert with, so that failures can be reported with their
+//! actual reason instead of an opaque contract error.
+
+use {
+    crate::errors::{revert_data, AsExecutionError},
+    ethcontract::{
+        common::abi::{self, ParamType, Token},
+        Address, U256,
+    },
+    std::fmt::{self, Display, Formatter},
+};
+
+/// Solidity types that appear as parameters of the custom errors.
+trait Param: Sized {
+    fn kind() -> ParamType;
+    fn from_token(token: Token) -> Option<Self>;
+}
+
+impl Param for Address {
+    fn kind() -> ParamType {
+        ParamType::Address
+    }
+
+    fn from_token(token: Token) -> Option<Self> {
+        token.into_address()
+    }
+}
+
+impl Param for U256 {
+    fn kind() -> ParamType {
+        ParamType::Uint(256)
+    }
+
+    fn from_token(token: Token) -> Option<Self> {
+        token.into_uint()
+    }
+}
+
+macro_rules! cow_amm_reverts {
+    ($($name:ident $({ $($field:ident: $ty:ty),* })? = $selector:literal;)*) => {
+        /// Custom error a CoW AMM contract reverted with. Variants are named
+        /// exactly like the Solidity errors.
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Debug, Eq, PartialEq)]
+        pub enum CowAmmRevert {
+            $($name $({ $($field: $ty),* })?,)*
+        }
+
+        impl CowAmmRevert {
+            /// Decodes ABI encoded revert data. Returns `None` if the data is
+            /// not one of the known custom errors.
+            pub fn decode(data: &[u8]) -> Option<Self> {
+                let selector = u32::from_be_bytes(data.get(..4)?.try_into().ok()?);
+                let params = &data[4..];
+                $(
+                    if selector == $selector {
+                        #[allow(unused_mut, unused_variables)]
+                        let mut tokens = abi::decode(
+                            &[$($(<$ty as Param>::kind()),*)?],
+                            params,
+                        )
+                        .ok()?
+                        .into_iter();
+                        return Some(Self::$name $({
+                            $($field: <$ty as Param>::from_token(tokens.next()?)?),*
+                        })?);
+                    }
+                )*
+                None
+            }
+
+            /// Name of the Solidity error, e.g. for use as a metrics label.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Self::$name { .. } => stringify!($name),)*
+                }
+            }
+
+            /// 4 byte selector of the Solidity error.
+            pub fn selector(&self) -> [u8; 4] {
+                match self {
+                    $(Self::$name { .. } => u32::to_be_bytes($selector),)*
+                }
+            }
+        }
+    };
+}
+
+// The variants and selectors are generated by the build script from the
+// custom errors declared in the vendored `BCowHelper` and `BCowPool` ABIs.
+include!(concat!(env!("OUT_DIR"), "/cow_amm_reverts.rs"));
+
+impl CowAmmRevert {
+    /// Decodes the revert data attached to a failed contract call.
+    pub fn from_method_error(err: &impl AsExecutionError) -> Option<Self> {
+        Self::decode(&revert_data(err)?)
+    }
+}
+
+impl Display for CowAmmRevert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            errors::{testing_node_error, testing_revert_error},
+            BCowHelper,
+            BCowPool,
+        },
+    };
+
+    #[test]
+    fn decodes_all_abi_errors() {
+        let helper = BCowHelper::raw_contract();
+        let pool = BCowPool::raw_contract();
+        let errors = helper
+            .interface
+            .abi
+            .errors
+            .values()
+            .chain(pool.interface.abi.errors.values())
+            .flatten();
+
+        for error in errors {
+            let params = error
+                .inputs
+                .iter()
+                .map(|input| match input.kind {
+                    ParamType::Address => Token::Address(Address::repeat_byte(1)),
+                    ParamType::Uint(_) => Token::Uint(U256::from(42)),
+                    ref kind => panic!("unexpected parameter type {kind:?}"),
+                })
+                .collect::<Vec<_>>();
+            let data = [&error.signature()[..4], &abi::encode(&params)[..]].concat();
+
+            let revert = CowAmmRevert::decode(&data)
+                .unwrap_or_else(|| panic!("failed to decode {}", error.name));
+            assert_eq!(revert.name(), error.name);
+            assert_eq!(revert.selector(), data[..4]);
+        }
+    }
+
+    #[test]
+    fn decodes_parameters() {
+        let data = [
+            &[0xfb, 0x8f, 0x41, 0xb2][..],
+            &abi::encode(&[
+                Token::Address(Address::repeat_byte(0x42)),
+                Token::Uint(U256::from(1)),
+                Token::Uint(U256::from(2)),
+            ])[..],
+        ]
+        .concat();
+
+        assert_eq!(
+            CowAmmRevert::decode(&data),
+            Some(CowAmmRevert::ERC20InsufficientAllowance {
+                spender: Address::repeat_byte(0x42),
+                allowance: U256::from(1),
+                needed: U256::from(2),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_or_malformed_data() {
+        assert_eq!(CowAmmRevert::decode(&[]), None);
+        assert_eq!(CowAmmRevert::decode(&[0xde, 0xad, 0xbe, 0xef]), None);
+        // `ERC20InsufficientAllowance` without its parameters.
+        assert_eq!(CowAmmRevert::decode(&[0xfb, 0x8f, 0x41, 0xb2]), None);
+    }
+
+    #[test]
+    fn decodes_method_errors() {
+        let err = testing_revert_error(&[0x20, 0x2e, 0x37, 0x7f]);
+        assert_eq!(
+            CowAmmRevert::from_method_error(&err),
+            Some(CowAmmRevert::BNum_MulOverflow)
+        );
+        assert_eq!(CowAmmRevert::from_method_error(&testing_node_error()), None);
+    }
+}
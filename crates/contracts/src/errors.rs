@@ -1,4 +1,7 @@
-use ethcontract::errors::{ExecutionError, MethodError};
+use ethcontract::{
+    errors::{ExecutionError, MethodError},
+    web3,
+};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum EthcontractErrorType {
@@ -41,6 +44,26 @@ impl AsExecutionError for ExecutionError {
     }
 }
 
+/// Returns the raw revert data of a failed call if the node reported it.
+///
+/// Nodes differ in how they report reverts: the data is either attached to
+/// the JSON RPC error or, for nodes that ethcontract recognizes, carried as
+/// a hex encoded revert reason.
+pub fn revert_data(err: &impl AsExecutionError) -> Option<Vec<u8>> {
+    match err.as_execution_error() {
+        ExecutionError::Web3(web3::Error::Rpc(err)) => {
+            decode_revert_data(err.data.as_ref()?.as_str()?)
+        }
+        ExecutionError::Revert(Some(reason)) => decode_revert_data(reason),
+        _ => None,
+    }
+}
+
+fn decode_revert_data(data: &str) -> Option<Vec<u8>> {
+    let data = data.trim_start_matches("Reverted ").strip_prefix("0x")?;
+    hex::decode(data).ok()
+}
+
 // Create an arbitrary error. Useful for testing.
 pub fn testing_node_error() -> MethodError {
     MethodError {
@@ -57,6 +80,18 @@ pub fn testing_contract_error() -> MethodError {
     }
 }
 
+// Create an error for a call that reverted with `data`. Useful for testing.
+pub fn testing_revert_error(data: &[u8]) -> MethodError {
+    MethodError {
+        signature: String::new(),
+        inner: ExecutionError::Web3(web3::Error::Rpc(ethcontract::jsonrpc::Error {
+            code: ethcontract::jsonrpc::ErrorCode::ServerError(3),
+            message: "execution reverted".to_string(),
+            data: Some(format!("0x{}", hex::encode(data)).into()),
+        })),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -76,4 +111,19 @@ mod tests {
             EthcontractErrorType::Contract
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn revert_data() {
+        let err = testing_revert_error(&[0x19, 0xaa, 0xd5, 0x73]);
+        assert_eq!(super::revert_data(&err), Some(vec![0x19, 0xaa, 0xd5, 0x73]));
+
+        let err = MethodError {
+            signature: String::new(),
+            inner: ExecutionError::Revert(Some("0x21081abf".to_string())),
+        };
+        assert_eq!(super::revert_data(&err), Some(vec![0x21, 0x08, 0x1a, 0xbf]));
+
+        assert_eq!(super::revert_data(&testing_node_error()), None);
+        assert_eq!(super::revert_data(&testing_contract_error()), None);
+    }
+}
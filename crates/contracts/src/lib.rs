@@ -1,6 +1,7 @@
 #![allow(clippy::let_unit_value)]
 
 pub use ethcontract;
+pub mod cow_amm_revert;
 pub mod errors;
 use {
     anyhow::{Result, anyhow, bail},
@@ -0,0 +1,188 @@
+//! Port of the `BMath` weighted pool math used by the CoW AMM contracts.
+//!
+//! Balances are token amounts, weights are denormalized and fees are 18
+//! decimal fixed-point numbers. Like [`crate::bnum`], every function rounds
+//! exactly like the contract and fails where it would revert.
+
+use {
+    crate::bnum::{self, BNumError, BONE},
+    ethcontract::U256,
+};
+
+/// Price of the output token in input tokens, including the swap fee.
+pub fn calc_spot_price(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    swap_fee: U256,
+) -> Result<U256, BNumError> {
+    let numer = bnum::bdiv(balance_in, weight_in)?;
+    let denom = bnum::bdiv(balance_out, weight_out)?;
+    let ratio = bnum::bdiv(numer, denom)?;
+    let scale = bnum::bdiv(BONE, bnum::bsub(BONE, swap_fee)?)?;
+    bnum::bmul(ratio, scale)
+}
+
+/// Amount of the output token received for selling `amount_in`.
+pub fn calc_out_given_in(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_in: U256,
+    swap_fee: U256,
+) -> Result<U256, BNumError> {
+    let weight_ratio = bnum::bdiv(weight_in, weight_out)?;
+    let adjusted_in = bnum::bmul(amount_in, bnum::bsub(BONE, swap_fee)?)?;
+    let y = bnum::bdiv(balance_in, bnum::badd(balance_in, adjusted_in)?)?;
+    let foo = bnum::bpow(y, weight_ratio)?;
+    let bar = bnum::bsub(BONE, foo)?;
+    bnum::bmul(balance_out, bar)
+}
+
+/// Amount of the input token needed for buying `amount_out`.
+pub fn calc_in_given_out(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_out: U256,
+    swap_fee: U256,
+) -> Result<U256, BNumError> {
+    let weight_ratio = bnum::bdiv(weight_out, weight_in)?;
+    let diff = bnum::bsub(balance_out, amount_out)?;
+    let y = bnum::bdiv(balance_out, diff)?;
+    let foo = bnum::bsub(bnum::bpow(y, weight_ratio)?, BONE)?;
+    // The contract subtracts the fee first, which decides the error when both
+    // operations fail.
+    let fee_complement = bnum::bsub(BONE, swap_fee)?;
+    bnum::bdiv(bnum::bmul(balance_in, foo)?, fee_complement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bone(n: u64) -> U256 {
+        BONE * n
+    }
+
+    // Expected values are the results of the `BCowHelper` view functions of
+    // the same name.
+
+    #[test]
+    fn prices_balanced_pool() {
+        assert_eq!(
+            calc_spot_price(bone(1000), bone(1), bone(1000), bone(1), U256::zero()).unwrap(),
+            BONE
+        );
+        // 80/20 pool with a 1% fee.
+        assert_eq!(
+            calc_spot_price(bone(1000), bone(4), bone(500), bone(1), BONE / 100).unwrap(),
+            U256::from(505_050_505_050_505_051_u64)
+        );
+    }
+
+    #[test]
+    fn swaps_in_balanced_pool() {
+        // 1000 * (1 - 1000 / 1100)
+        assert_eq!(
+            calc_out_given_in(
+                bone(1000),
+                bone(1),
+                bone(1000),
+                bone(1),
+                bone(100),
+                U256::zero()
+            )
+            .unwrap(),
+            U256::from(90_909_090_909_090_909_000_u128)
+        );
+        // 1000 * (1000 / 900 - 1)
+        assert_eq!(
+            calc_in_given_out(
+                bone(1000),
+                bone(1),
+                bone(1000),
+                bone(1),
+                bone(100),
+                U256::zero()
+            )
+            .unwrap(),
+            U256::from(111_111_111_111_111_111_000_u128)
+        );
+    }
+
+    #[test]
+    fn swaps_in_weighted_pool() {
+        assert_eq!(
+            calc_out_given_in(
+                bone(1000),
+                bone(4),
+                bone(500),
+                bone(1),
+                bone(10),
+                BONE / 100
+            )
+            .unwrap(),
+            U256::from(19_319_487_509_479_948_500_u128)
+        );
+        assert_eq!(
+            calc_in_given_out(
+                bone(1000),
+                bone(4),
+                bone(500),
+                bone(1),
+                bone(10),
+                BONE / 100
+            )
+            .unwrap(),
+            U256::from(5_114_598_989_454_825_253_u64)
+        );
+    }
+
+    #[test]
+    fn reverts_like_the_contract() {
+        // Buying the whole balance divides by zero.
+        assert_eq!(
+            calc_in_given_out(
+                bone(1000),
+                bone(1),
+                bone(1000),
+                bone(1),
+                bone(1000),
+                U256::zero()
+            ),
+            Err(BNumError::DivZero)
+        );
+        assert_eq!(
+            calc_in_given_out(
+                bone(1000),
+                bone(1),
+                bone(1000),
+                bone(1),
+                bone(1001),
+                U256::zero()
+            ),
+            Err(BNumError::SubUnderflow)
+        );
+        // Buying more than half of the balance needs a base above 2.
+        assert_eq!(
+            calc_in_given_out(
+                bone(1000),
+                bone(1),
+                bone(1000),
+                bone(1),
+                bone(600),
+                U256::zero()
+            ),
+            Err(BNumError::BPowBaseTooHigh)
+        );
+        // A fee above 100% fails before the overflowing multiplication.
+        assert_eq!(
+            calc_in_given_out(U256::MAX, bone(1), bone(1000), bone(1), bone(100), bone(2)),
+            Err(BNumError::SubUnderflow)
+        );
+    }
+}
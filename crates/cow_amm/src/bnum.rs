@@ -0,0 +1,451 @@
+//! Port of the `BNum` fixed-point library used by the CoW AMM contracts.
+//!
+//! Numbers are 18 decimal fixed-point values. Every function rounds exactly
+//! like its Solidity counterpart and fails where the contract would revert.
+
+use ethcontract::U256;
+
+/// Fixed-point representation of `1`.
+pub const BONE: U256 = U256([1_000_000_000_000_000_000, 0, 0, 0]);
+/// Smallest base accepted by [`bpow`].
+pub const MIN_BPOW_BASE: U256 = U256([1, 0, 0, 0]);
+/// Largest base accepted by [`bpow`].
+pub const MAX_BPOW_BASE: U256 = U256([1_999_999_999_999_999_999, 0, 0, 0]);
+/// Precision at which [`bpow_approx`] stops adding terms.
+pub const BPOW_PRECISION: U256 = U256([100_000_000, 0, 0, 0]);
+
+/// The `BNum_*` custom errors the contracts revert with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum BNumError {
+    #[error("BNum_AddOverflow")]
+    AddOverflow,
+    #[error("BNum_SubUnderflow")]
+    SubUnderflow,
+    #[error("BNum_MulOverflow")]
+    MulOverflow,
+    #[error("BNum_DivZero")]
+    DivZero,
+    #[error("BNum_DivInternal")]
+    DivInternal,
+    #[error("BNum_BPowBaseTooLow")]
+    BPowBaseTooLow,
+    #[error("BNum_BPowBaseTooHigh")]
+    BPowBaseTooHigh,
+}
+
+pub fn btoi(a: U256) -> U256 {
+    a / BONE
+}
+
+pub fn bfloor(a: U256) -> U256 {
+    btoi(a) * BONE
+}
+
+pub fn badd(a: U256, b: U256) -> Result<U256, BNumError> {
+    a.checked_add(b).ok_or(BNumError::AddOverflow)
+}
+
+pub fn bsub(a: U256, b: U256) -> Result<U256, BNumError> {
+    match bsub_sign(a, b) {
+        (c, false) => Ok(c),
+        (_, true) => Err(BNumError::SubUnderflow),
+    }
+}
+
+/// Returns `|a - b|` and whether the difference is negative.
+pub fn bsub_sign(a: U256, b: U256) -> (U256, bool) {
+    if a >= b {
+        (a - b, false)
+    } else {
+        (b - a, true)
+    }
+}
+
+/// Multiplies two fixed-point numbers, rounding half up.
+pub fn bmul(a: U256, b: U256) -> Result<U256, BNumError> {
+    let c0 = a.checked_mul(b).ok_or(BNumError::MulOverflow)?;
+    let c1 = c0.checked_add(BONE / 2).ok_or(BNumError::MulOverflow)?;
+    Ok(c1 / BONE)
+}
+
+/// Divides two fixed-point numbers, rounding half up.
+pub fn bdiv(a: U256, b: U256) -> Result<U256, BNumError> {
+    if b.is_zero() {
+        return Err(BNumError::DivZero);
+    }
+    let c0 = a.checked_mul(BONE).ok_or(BNumError::DivInternal)?;
+    let c1 = c0.checked_add(b / 2).ok_or(BNumError::DivInternal)?;
+    Ok(c1 / b)
+}
+
+/// Raises a fixed-point number to a whole power.
+pub fn bpowi(mut a: U256, mut n: U256) -> Result<U256, BNumError> {
+    let mut z = if n.bit(0) { a } else { BONE };
+    n >>= 1;
+    while !n.is_zero() {
+        a = bmul(a, a)?;
+        if n.bit(0) {
+            z = bmul(z, a)?;
+        }
+        n >>= 1;
+    }
+    Ok(z)
+}
+
+/// Raises a fixed-point base in `[MIN_BPOW_BASE, MAX_BPOW_BASE]` to a
+/// fixed-point exponent.
+pub fn bpow(base: U256, exp: U256) -> Result<U256, BNumError> {
+    if base < MIN_BPOW_BASE {
+        return Err(BNumError::BPowBaseTooLow);
+    }
+    if base > MAX_BPOW_BASE {
+        return Err(BNumError::BPowBaseTooHigh);
+    }
+
+    let whole = bfloor(exp);
+    let remain = bsub(exp, whole)?;
+    let whole_pow = bpowi(base, btoi(whole))?;
+    if remain.is_zero() {
+        return Ok(whole_pow);
+    }
+
+    let partial_result = bpow_approx(base, remain, BPOW_PRECISION)?;
+    bmul(whole_pow, partial_result)
+}
+
+/// Approximates `base ^ exp` for a fractional `exp` with a binomial series,
+/// adding terms until they become smaller than `precision`.
+pub fn bpow_approx(base: U256, exp: U256, precision: U256) -> Result<U256, BNumError> {
+    let a = exp;
+    let (x, xneg) = bsub_sign(base, BONE);
+    let mut term = BONE;
+    let mut sum = term;
+    let mut negative = false;
+
+    let mut i = U256::one();
+    while term >= precision {
+        let big_k = i * BONE;
+        let (c, cneg) = bsub_sign(a, bsub(big_k, BONE)?);
+        term = bmul(term, bmul(c, x)?)?;
+        term = bdiv(term, big_k)?;
+        if term.is_zero() {
+            break;
+        }
+
+        if xneg {
+            negative = !negative;
+        }
+        if cneg {
+            negative = !negative;
+        }
+        sum = if negative {
+            bsub(sum, term)?
+        } else {
+            badd(sum, term)?
+        };
+        i += U256::one();
+    }
+
+    Ok(sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{bmath, testing},
+        proptest::prelude::*,
+    };
+
+    fn bone(n: u64) -> U256 {
+        BONE * n
+    }
+
+    #[test]
+    fn rounds_half_up() {
+        assert_eq!(bmul(U256::from(1), BONE / 2).unwrap(), U256::from(1));
+        assert_eq!(bmul(U256::from(1), BONE / 2 - 1).unwrap(), U256::zero());
+        assert_eq!(bdiv(U256::from(1), bone(2)).unwrap(), U256::from(1));
+        assert_eq!(
+            bdiv(bone(1), U256::from(3)).unwrap(),
+            U256::from_dec_str("333333333333333333333333333333333333").unwrap()
+        );
+    }
+
+    #[test]
+    fn reports_overflows() {
+        assert_eq!(badd(U256::MAX, U256::one()), Err(BNumError::AddOverflow));
+        assert_eq!(bsub(U256::zero(), U256::one()), Err(BNumError::SubUnderflow));
+        assert_eq!(bmul(U256::MAX, U256::from(2)), Err(BNumError::MulOverflow));
+        assert_eq!(bmul(U256::MAX, U256::one()), Err(BNumError::MulOverflow));
+        assert_eq!(bdiv(U256::one(), U256::zero()), Err(BNumError::DivZero));
+        assert_eq!(bdiv(U256::MAX, U256::one()), Err(BNumError::DivInternal));
+        assert_eq!(bpow(U256::zero(), BONE), Err(BNumError::BPowBaseTooLow));
+        assert_eq!(bpow(bone(2), BONE), Err(BNumError::BPowBaseTooHigh));
+    }
+
+    #[test]
+    fn whole_powers() {
+        assert_eq!(bpowi(bone(2), U256::from(10)).unwrap(), bone(1024));
+        assert_eq!(bpowi(bone(3), U256::zero()).unwrap(), BONE);
+        assert_eq!(bpow(BONE / 2, bone(2)).unwrap(), BONE / 4);
+    }
+
+    #[test]
+    fn fractional_powers() {
+        // 0.25 ^ 0.5 = 0.5, the series is accurate to about 1e-9 this far
+        // away from 1.
+        let result = bpow(BONE / 4, BONE / 2).unwrap();
+        assert_eq!(result, U256::from(500_000_000_254_041_274_u64));
+
+        // 0.9 ^ 0.5
+        let result = bpow(bone(9) / 10, BONE / 2).unwrap();
+        assert_eq!(result, U256::from(948_683_298_051_528_931_u64));
+    }
+
+    #[test]
+    fn constants_match_contract() {
+        for (name, value) in [
+            ("BONE", BONE),
+            ("BPOW_PRECISION", BPOW_PRECISION),
+            ("MIN_BPOW_BASE", MIN_BPOW_BASE),
+            ("MAX_BPOW_BASE", MAX_BPOW_BASE),
+        ] {
+            assert_eq!(testing::call_uint(name, &[]), Some(Ok(value)), "{name}");
+        }
+    }
+
+    // The contracts only expose `BNum` through the `BMath` functions. For
+    // some inputs those reduce to a single operation, which compares every
+    // operation directly on its rounding edges, reverts and bounds.
+
+    fn ours(result: Result<U256, BNumError>) -> Result<U256, String> {
+        result.map_err(|err| err.to_string())
+    }
+
+    fn contract(function: &str, args: &[U256]) -> Result<U256, String> {
+        testing::call_uint(function, args).expect("out of gas")
+    }
+
+    /// `calcSpotPrice` with unit weights and no fee is `bdiv(balanceIn,
+    /// balanceOut)`.
+    fn contract_bdiv(a: U256, b: U256) -> Result<U256, String> {
+        contract("calcSpotPrice", &[a, BONE, b, BONE, U256::zero()])
+    }
+
+    /// `calcOutGivenIn` with unit weights is `bmul(balanceOut, 1 - y)` for
+    /// `y = balanceIn / (balanceIn + amountIn)`, so splitting `1` into a
+    /// balance in of `1 - b` and an amount in of `b` multiplies by `b`.
+    fn contract_bmul(a: U256, b: U256) -> Result<U256, String> {
+        contract("calcOutGivenIn", &[BONE - b, BONE, a, BONE, b, U256::zero()])
+    }
+
+    /// With a unit balance and weight out, `calcOutGivenIn` is `1 - bpow(y,
+    /// weightIn)`, and with a unit balance and weight in, `calcInGivenOut` is
+    /// `bpow(y, weightOut) - 1` for `y = balanceOut / (balanceOut -
+    /// amountOut)`. The amounts make `y` exactly `base`.
+    fn contract_bpow(base: U256, exp: U256) -> Result<U256, String> {
+        if base <= BONE {
+            let args = [base, exp, BONE, BONE, BONE - base, U256::zero()];
+            contract("calcOutGivenIn", &args).map(|amount_out| BONE - amount_out)
+        } else {
+            let args = [BONE, BONE, base, exp, base - BONE, U256::zero()];
+            contract("calcInGivenOut", &args).map(|amount_in| amount_in + BONE)
+        }
+    }
+
+    #[test]
+    fn bsub_matches_contract() {
+        // `calcSpotPrice` of unit balances and weights is `bdiv(1, bsub(1,
+        // fee))`.
+        for fee in [U256::zero(), U256::one(), BONE - 1, BONE, BONE + 1, U256::MAX] {
+            let expected = contract("calcSpotPrice", &[BONE, BONE, BONE, BONE, fee]);
+            let result = bsub(BONE, fee).and_then(|complement| bdiv(BONE, complement));
+            assert_eq!(ours(result), expected, "bsub(BONE, {fee})");
+        }
+
+        // `calcInGivenOut` starts with `bsub(balanceOut, amountOut)`.
+        for (a, b) in [
+            (U256::zero(), U256::one()),
+            (bone(5), bone(5) + 1),
+            (U256::MAX - 1, U256::MAX),
+        ] {
+            let expected = contract("calcInGivenOut", &[BONE, BONE, a, BONE, b, U256::zero()]);
+            assert_eq!(ours(bsub(a, b)), expected, "bsub({a}, {b})");
+        }
+    }
+
+    #[test]
+    fn bmul_matches_contract() {
+        for (a, b) in [
+            // Rounds half up.
+            (U256::one(), BONE / 2),
+            (U256::one(), BONE / 2 - 1),
+            (U256::from(3), BONE / 2),
+            (bone(7), BONE / 3),
+            (U256::from(123_456_789_123_456_789_u64), U256::from(987_654_321)),
+            (bone(1000), U256::zero()),
+            // Overflows in the product or when rounding it.
+            (U256::MAX, U256::from(2)),
+            (U256::MAX, U256::one()),
+            (U256::MAX / 2, BONE / 2),
+        ] {
+            assert_eq!(ours(bmul(a, b)), contract_bmul(a, b), "bmul({a}, {b})");
+        }
+    }
+
+    #[test]
+    fn bdiv_matches_contract() {
+        let largest = U256::MAX / BONE;
+        for (a, b) in [
+            // Rounds half up.
+            (U256::one(), bone(2)),
+            (U256::one(), bone(2) + 1),
+            (U256::one(), bone(2) - 1),
+            (bone(1), U256::from(3)),
+            (bone(2), U256::from(3)),
+            (U256::from(123_456_789), U256::from(1_000_000_007)),
+            (largest, bone(1)),
+            // Division by zero, and overflows when scaling or rounding.
+            (U256::from(7), U256::zero()),
+            (largest + 1, bone(1)),
+            (largest, bone(2)),
+        ] {
+            assert_eq!(ours(bdiv(a, b)), contract_bdiv(a, b), "bdiv({a}, {b})");
+        }
+    }
+
+    #[test]
+    fn bpowi_matches_contract() {
+        for (base, n) in [
+            (BONE / 2, 3),
+            (BONE / 3, 7),
+            (bone(9) / 10, 0),
+            (bone(3) / 2, 100),
+            (MIN_BPOW_BASE, 1),
+            (MIN_BPOW_BASE, 2),
+            (MAX_BPOW_BASE, 10),
+            (MAX_BPOW_BASE, 135),
+            // Overflows while squaring.
+            (bone(19) / 10, 256),
+        ] {
+            let result = bpowi(base, U256::from(n));
+            assert_eq!(ours(result), contract_bpow(base, bone(n)), "bpowi({base}, {n})");
+        }
+    }
+
+    #[test]
+    fn bpow_matches_contract() {
+        for (base, exp) in [
+            // The bounds of the base.
+            (MIN_BPOW_BASE - 1, BONE),
+            (MIN_BPOW_BASE, BONE),
+            (MAX_BPOW_BASE, BONE),
+            (MAX_BPOW_BASE + 1, BONE / 2),
+            // Whole and fractional parts of the exponent.
+            (BONE / 4, bone(3) / 2),
+            (bone(9) / 10, bone(5) / 2),
+            (bone(3) / 2, bone(7) / 3),
+            (BONE / 2, bone(4) + 1),
+            (BONE, BONE / 3),
+        ] {
+            assert_eq!(ours(bpow(base, exp)), contract_bpow(base, exp), "bpow({base}, {exp})");
+        }
+    }
+
+    #[test]
+    fn bpow_approx_matches_contract() {
+        // `bpow` only approximates exponents below one.
+        for (base, exp) in [
+            (BONE / 2, BONE / 2),
+            (BONE / 100, BONE / 4),
+            (bone(9) / 10, U256::one()),
+            (bone(11) / 10, BONE - 1),
+            (bone(3) / 2, BONE / 3),
+            (bone(19) / 10, bone(3) / 4),
+        ] {
+            let result = bpow_approx(base, exp, BPOW_PRECISION);
+            assert_eq!(ours(result), contract_bpow(base, exp), "bpow_approx({base}, {exp})");
+        }
+    }
+
+    // The `BMath` functions use every operation, so the port is also compared
+    // against them on random inputs.
+
+    fn amount() -> impl Strategy<Value = U256> {
+        // Mostly token amounts, but also values large enough to overflow.
+        prop_oneof![
+            4 => (1_u64..1_000_000, 0_usize..30)
+                .prop_map(|(mantissa, exp)| U256::from(mantissa) * U256::exp10(exp)),
+            1 => any::<[u64; 4]>().prop_map(U256),
+        ]
+    }
+
+    fn weight() -> impl Strategy<Value = U256> {
+        (100_000_000_000_000_000_u128..=50_000_000_000_000_000_000).prop_map(U256::from)
+    }
+
+    fn fee() -> impl Strategy<Value = U256> {
+        prop_oneof![
+            Just(U256::zero()),
+            (0_u64..=BONE.as_u64()).prop_map(U256::from),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn spot_price_matches_contract(
+            balance_in in amount(),
+            weight_in in weight(),
+            balance_out in amount(),
+            weight_out in weight(),
+            fee in fee(),
+        ) {
+            let expected = testing::call_uint(
+                "calcSpotPrice",
+                &[balance_in, weight_in, balance_out, weight_out, fee],
+            );
+            prop_assume!(expected.is_some());
+            let result =
+                bmath::calc_spot_price(balance_in, weight_in, balance_out, weight_out, fee);
+            prop_assert_eq!(result.map_err(|err| err.to_string()), expected.unwrap());
+        }
+
+        #[test]
+        fn out_given_in_matches_contract(
+            balance_in in amount(),
+            weight_in in weight(),
+            balance_out in amount(),
+            weight_out in weight(),
+            amount_in in amount(),
+            fee in fee(),
+        ) {
+            let args = [balance_in, weight_in, balance_out, weight_out, amount_in, fee];
+            let expected = testing::call_uint("calcOutGivenIn", &args);
+            // Out of gas in `bpowApprox`, which the port does not model.
+            prop_assume!(expected.is_some());
+            let result = bmath::calc_out_given_in(
+                balance_in, weight_in, balance_out, weight_out, amount_in, fee,
+            );
+            prop_assert_eq!(result.map_err(|err| err.to_string()), expected.unwrap());
+        }
+
+        #[test]
+        fn in_given_out_matches_contract(
+            balance_in in amount(),
+            weight_in in weight(),
+            balance_out in amount(),
+            weight_out in weight(),
+            amount_out in amount(),
+            fee in fee(),
+        ) {
+            let args = [balance_in, weight_in, balance_out, weight_out, amount_out, fee];
+            let expected = testing::call_uint("calcInGivenOut", &args);
+            prop_assume!(expected.is_some());
+            let result = bmath::calc_in_given_out(
+                balance_in, weight_in, balance_out, weight_out, amount_out, fee,
+            );
+            prop_assert_eq!(result.map_err(|err| err.to_string()), expected.unwrap());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Static gas costs of settling a CoW AMM template order.
+//!
+//! The costs are conservative estimates of the storage and call accesses each
+//! step performs when the pool and its tokens are first touched in the
+//! settlement. They are meant for scoring solutions before they are simulated;
+//! `interactions::simulation` measures the exact amount for a given state, and
+//! its tests check that the estimates stay within 50% of that amount for swaps
+//! and wrapping. The pool costs can only be checked against a recorded
+//! `BCoWPool`, since the offline fixture has no pool bytecode.
+
+use {
+    crate::{helper::TemplateOrder, local_helper},
+    ethcontract::U256,
+    hex_literal::hex,
+    model::interaction::InteractionData,
+};
+
+/// `BCoWPool.commit` pre-interaction, which stores the order hash in
+/// transient storage.
+pub const COMMIT: u64 = 10_000;
+
+/// `BCoWPool.isValidSignature`, which hashes the order and checks the trade
+/// against the pool balances and weights.
+pub const IS_VALID_SIGNATURE: u64 = 40_000;
+
+/// ERC20 transfer of one side of the trade between pool and settlement.
+pub const ERC20_TRANSFER: u64 = 30_000;
+
+/// `BCoWPool.joinPool` or `BCoWPool.exitPool` without token transfers, which
+/// mints or burns the pool tokens.
+pub const POOL_JOIN_EXIT: u64 = 40_000;
+
+/// `BCoWPool.swapExactAmountIn` or `BCoWPool.swapExactAmountOut` without
+/// token transfers, which prices the swap with the weighted math.
+pub const POOL_SWAP: u64 = 40_000;
+
+/// `ERC20.approve` of a pool by the settlement contract.
+pub const ERC20_APPROVE: u64 = 30_000;
+
+/// `WETH9.deposit` or `WETH9.withdraw`, which wrap or unwrap native ETH.
+pub const WETH_WRAP: u64 = 30_000;
+
+/// Any interaction that is not known to the estimate.
+pub const UNKNOWN_INTERACTION: u64 = 50_000;
+
+const JOIN_POOL: [u8; 4] = hex!("4f69c0d4");
+const EXIT_POOL: [u8; 4] = hex!("b02f0b73");
+const SWAP_EXACT_AMOUNT_IN: [u8; 4] = hex!("8201aa3f");
+const SWAP_EXACT_AMOUNT_OUT: [u8; 4] = hex!("7c5e9ea4");
+const APPROVE: [u8; 4] = hex!("095ea7b3");
+const WETH_DEPOSIT: [u8; 4] = hex!("d0e30db0");
+const WETH_WITHDRAW: [u8; 4] = hex!("2e1a7d4d");
+
+impl TemplateOrder {
+    /// Gas the settlement spends on this template: its interactions, the
+    /// signature verification and the transfers of both traded tokens.
+    pub fn estimate_gas(&self) -> u64 {
+        self.pre_interactions
+            .iter()
+            .chain(&self.post_interactions)
+            .map(interaction_gas)
+            .sum::<u64>()
+            + IS_VALID_SIGNATURE
+            + 2 * ERC20_TRANSFER
+    }
+
+    /// Sets [`TemplateOrder::gas_estimate`] for the current interactions.
+    pub fn update_gas_estimate(&mut self) {
+        self.gas_estimate = self.estimate_gas();
+    }
+}
+
+/// Gas of a single interaction, based on the function it calls.
+pub fn interaction_gas(interaction: &InteractionData) -> u64 {
+    let call_data = interaction.call_data.as_slice();
+    match call_data
+        .get(..4)
+        .and_then(|selector| <[u8; 4]>::try_from(selector).ok())
+    {
+        Some(local_helper::COMMIT) => COMMIT,
+        // Joins and exits move every pool token between the pool and the
+        // settlement.
+        Some(JOIN_POOL | EXIT_POOL) => match amounts_len(call_data) {
+            Some(tokens) => POOL_JOIN_EXIT + tokens * ERC20_TRANSFER,
+            None => UNKNOWN_INTERACTION,
+        },
+        // Pool swaps move both of their tokens.
+        Some(SWAP_EXACT_AMOUNT_IN | SWAP_EXACT_AMOUNT_OUT) => POOL_SWAP + 2 * ERC20_TRANSFER,
+        Some(APPROVE) => ERC20_APPROVE,
+        Some(WETH_DEPOSIT | WETH_WITHDRAW) => WETH_WRAP,
+        _ => UNKNOWN_INTERACTION,
+    }
+}
+
+/// Length of the amounts array of `joinPool(uint256,uint256[])` and
+/// `exitPool(uint256,uint256[])` calldata.
+fn amounts_len(call_data: &[u8]) -> Option<u64> {
+    let offset = U256::from_big_endian(call_data.get(36..68)?);
+    let start = usize::try_from(offset).ok()?.checked_add(4)?;
+    let len = U256::from_big_endian(call_data.get(start..start.checked_add(32)?)?);
+    u64::try_from(len).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        ethcontract::H160,
+        model::{order::OrderData, signature::Signature},
+    };
+
+    fn interaction(call_data: Vec<u8>) -> InteractionData {
+        InteractionData {
+            target: H160([0x11; 20]),
+            value: U256::zero(),
+            call_data,
+        }
+    }
+
+    fn pool_call(selector: [u8; 4], amounts: &[u64]) -> Vec<u8> {
+        let mut call_data = selector.to_vec();
+        let mut word = |value: U256| {
+            let mut bytes = [0; 32];
+            value.to_big_endian(&mut bytes);
+            call_data.extend(bytes);
+        };
+        word(U256::exp10(18));
+        word(U256::from(64));
+        word(U256::from(amounts.len()));
+        for amount in amounts {
+            word(U256::from(*amount));
+        }
+        call_data
+    }
+
+    fn template(
+        pre_interactions: Vec<InteractionData>,
+        post_interactions: Vec<InteractionData>,
+    ) -> TemplateOrder {
+        TemplateOrder {
+            order: OrderData::default(),
+            signature: Signature::Eip1271(Vec::new()),
+            pre_interactions,
+            post_interactions,
+            slippage_bps: None,
+            gas_estimate: 0,
+        }
+    }
+
+    #[test]
+    fn classifies_interactions() {
+        let commit = [local_helper::COMMIT.as_slice(), &[0; 32]].concat();
+        assert_eq!(interaction_gas(&interaction(commit)), COMMIT);
+        assert_eq!(
+            interaction_gas(&interaction(pool_call(JOIN_POOL, &[1, 2]))),
+            POOL_JOIN_EXIT + 2 * ERC20_TRANSFER
+        );
+        assert_eq!(
+            interaction_gas(&interaction(pool_call(EXIT_POOL, &[1, 2, 3]))),
+            POOL_JOIN_EXIT + 3 * ERC20_TRANSFER
+        );
+        assert_eq!(
+            interaction_gas(&interaction(SWAP_EXACT_AMOUNT_IN.to_vec())),
+            POOL_SWAP + 2 * ERC20_TRANSFER
+        );
+        assert_eq!(
+            interaction_gas(&interaction(WETH_DEPOSIT.to_vec())),
+            WETH_WRAP
+        );
+        // Truncated calldata cannot be priced per token.
+        assert_eq!(
+            interaction_gas(&interaction(JOIN_POOL.to_vec())),
+            UNKNOWN_INTERACTION
+        );
+        assert_eq!(
+            interaction_gas(&interaction(vec![0xaa; 4])),
+            UNKNOWN_INTERACTION
+        );
+        assert_eq!(
+            interaction_gas(&interaction(Vec::new())),
+            UNKNOWN_INTERACTION
+        );
+    }
+
+    #[test]
+    fn sums_template_costs() {
+        let commit = interaction([local_helper::COMMIT.as_slice(), &[0; 32]].concat());
+        let swap = template(vec![commit.clone()], Vec::new());
+        assert_eq!(
+            swap.estimate_gas(),
+            COMMIT + IS_VALID_SIGNATURE + 2 * ERC20_TRANSFER
+        );
+
+        let mut join = template(
+            vec![commit],
+            vec![interaction(pool_call(JOIN_POOL, &[1, 2]))],
+        );
+        assert_eq!(
+            join.estimate_gas(),
+            swap.estimate_gas() + POOL_JOIN_EXIT + 2 * ERC20_TRANSFER
+        );
+        assert_eq!(join.gas_estimate, 0);
+        join.update_gas_estimate();
+        assert_eq!(join.gas_estimate, join.estimate_gas());
+    }
+}
@@ -51,14 +51,14 @@ impl Amm {
     pub async fn template_order(&self, prices: Vec<U256>) -> Result<TemplateOrder> {
         let (order, pre_interactions, post_interactions, signature) =
         self.helper.order(self.address, prices).call().await?; //order_from_sell_amount
-        self.convert_orders_response(order, signature, pre_interactions, post_interactions)
+        convert_orders_response(order, signature, pre_interactions, post_interactions)
     }
     /// Method for returning the canonical order required to satisfy the
     /// pool's invariants, given a buy token and exact buy amount.
     pub async fn template_order_from_buy_amount(&self, buy_token: Address, buy_amount: U256) -> Result<TemplateOrder> {
         let (order, pre_interactions, post_interactions, signature) =
         self.helper.order_from_buy_amount(self.address, buy_token, buy_amount).call().await?; 
-        self.convert_orders_response(order, signature, pre_interactions, post_interactions)
+        convert_orders_response(order, signature, pre_interactions, post_interactions)
     }
     /// Method for returning the canonical order required to satisfy the
     /// pool's invariants, given a sell token and a **tentative** sell amount.
@@ -67,7 +67,7 @@ impl Amm {
     pub async fn template_order_from_sell_amount(&self, sell_token: Address, sell_amount: U256) -> Result<TemplateOrder> {
         let (order, pre_interactions, post_interactions, signature) =
         self.helper.order_from_sell_amount(self.address, sell_token, sell_amount).call().await?; 
-        self.convert_orders_response(order, signature, pre_interactions, post_interactions)
+        convert_orders_response(order, signature, pre_interactions, post_interactions)
     }
   
     /// Generates a template order to rebalance the AMM but also verifies that
@@ -80,20 +80,7 @@ impl Amm {
         domain_separator: &DomainSeparator,
     ) -> Result<TemplateOrder> {
         let template = self.template_order(prices).await?;
-
-        // A buggy helper contract could return a signature that is actually not valid.
-        // To avoid issues caused by that we check the validity of the signature.
-        let hash = hashed_eip712_message(domain_separator, &template.order.hash_struct());
-        validator
-            .validate_signature_and_get_additional_gas(SignatureCheck {
-                signer: self.address,
-                hash,
-                signature: template.signature.to_bytes(),
-                interactions: template.pre_interactions.clone(),
-                balance_override: None,
-            })
-            .await
-            .context("invalid signature")?;
+        self.validate_template(&template, validator, domain_separator).await?;
 
         Ok(template)
     }
@@ -108,20 +95,7 @@ impl Amm {
         domain_separator: &DomainSeparator,
     ) -> Result<TemplateOrder> {
         let template = self.template_order_from_buy_amount(buy_token, buy_amount).await?;
-
-        // A buggy helper contract could return a signature that is actually not valid.
-        // To avoid issues caused by that we check the validity of the signature.
-        let hash = hashed_eip712_message(domain_separator, &template.order.hash_struct());
-        validator
-            .validate_signature_and_get_additional_gas(SignatureCheck {
-                signer: self.address,
-                hash,
-                signature: template.signature.to_bytes(),
-                interactions: template.pre_interactions.clone(),
-                balance_override: None,
-            })
-            .await
-            .context("invalid signature")?;
+        self.validate_template(&template, validator, domain_separator).await?;
 
         Ok(template)
     }
@@ -136,7 +110,19 @@ impl Amm {
         domain_separator: &DomainSeparator,
     ) -> Result<TemplateOrder> {
         let template = self.template_order_from_sell_amount(sell_token, sell_amount).await?;
+        self.validate_template(&template, validator, domain_separator).await?;
+
+        Ok(template)
+    }
 
+    /// Verifies that the signature of `template` is valid for this pool after
+    /// executing its pre-interactions.
+    pub async fn validate_template(
+        &self,
+        template: &TemplateOrder,
+        validator: &dyn SignatureValidating,
+        domain_separator: &DomainSeparator,
+    ) -> Result<()> {
         // A buggy helper contract could return a signature that is actually not valid.
         // To avoid issues caused by that we check the validity of the signature.
         let hash = hashed_eip712_message(domain_separator, &template.order.hash_struct());
@@ -151,51 +137,7 @@ impl Amm {
             .await
             .context("invalid signature")?;
 
-        Ok(template)
-    }
-
-    /// Converts a successful response of the BCowHelper into domain types.
-    /// Can be used for any contract that correctly implements the CoW AMM (BCoW)
-    /// helper interface.
-    fn convert_orders_response(
-        &self,
-        order: RawOrder,
-        signature: Bytes<Vec<u8>>,
-        pre_interactions: Vec<RawInteraction>,
-        post_interactions: Vec<RawInteraction>,
-    ) -> Result<TemplateOrder> {
-        let order = OrderData {
-            sell_token: order.0,
-            buy_token: order.1,
-            receiver: Some(order.2),
-            sell_amount: order.3,
-            buy_amount: order.4,
-            valid_to: order.5,
-            app_data: AppDataHash(order.6.0),
-            fee_amount: order.7,
-            kind: convert_kind(&order.8.0)?,
-            partially_fillable: order.9,
-            sell_token_balance: convert_sell_token_source(&order.10.0)?,
-            buy_token_balance: convert_buy_token_destination(&order.11.0)?,
-        };
-
-        let pre_interactions = convert_interactions(pre_interactions);
-        let post_interactions = convert_interactions(post_interactions);
-
-        // The settlement contract expects a signature composed of 2 parts: the
-        // signer address and the actual signature bytes.
-        // The helper contract returns exactly that format but in our code base we
-        // expect the signature to not already include the signer address (the parts
-        // will be concatenated in the encoding logic) so we discard the first 20 bytes.
-        let raw_signature = signature.0.into_iter().skip(20).collect();
-        let signature = Signature::Eip1271(raw_signature);
-
-        Ok(TemplateOrder {
-            order,
-            signature,
-            pre_interactions,
-            post_interactions,
-        })
+        Ok(())
     }
 }
 
@@ -212,6 +154,60 @@ pub struct TemplateOrder {
     /// Transactions to be executed after transfering funds out of the
     /// settlement contract.
     pub post_interactions: Vec<InteractionData>,
+    /// Slippage tolerance in basis points that was applied to the amounts of
+    /// pool joins or exits added to the interactions, if any.
+    pub slippage_bps: Option<u32>,
+    /// Gas the settlement spends on this template, see
+    /// [`TemplateOrder::estimate_gas`]. Has to be updated with
+    /// [`TemplateOrder::update_gas_estimate`] when interactions are added.
+    pub gas_estimate: u64,
+}
+
+/// Converts a successful response of the BCowHelper into domain types.
+/// Can be used for any contract that correctly implements the CoW AMM (BCoW)
+/// helper interface.
+pub(crate) fn convert_orders_response(
+    order: RawOrder,
+    signature: Bytes<Vec<u8>>,
+    pre_interactions: Vec<RawInteraction>,
+    post_interactions: Vec<RawInteraction>,
+) -> Result<TemplateOrder> {
+    let order = OrderData {
+        sell_token: order.0,
+        buy_token: order.1,
+        receiver: Some(order.2),
+        sell_amount: order.3,
+        buy_amount: order.4,
+        valid_to: order.5,
+        app_data: AppDataHash(order.6.0),
+        fee_amount: order.7,
+        kind: convert_kind(&order.8.0)?,
+        partially_fillable: order.9,
+        sell_token_balance: convert_sell_token_source(&order.10.0)?,
+        buy_token_balance: convert_buy_token_destination(&order.11.0)?,
+    };
+
+    let pre_interactions = convert_interactions(pre_interactions);
+    let post_interactions = convert_interactions(post_interactions);
+
+    // The settlement contract expects a signature composed of 2 parts: the
+    // signer address and the actual signature bytes.
+    // The helper contract returns exactly that format but in our code base we
+    // expect the signature to not already include the signer address (the parts
+    // will be concatenated in the encoding logic) so we discard the first 20 bytes.
+    let raw_signature = signature.0.into_iter().skip(20).collect();
+    let signature = Signature::Eip1271(raw_signature);
+
+    let mut template = TemplateOrder {
+        order,
+        signature,
+        pre_interactions,
+        post_interactions,
+        slippage_bps: None,
+        gas_estimate: 0,
+    };
+    template.update_gas_estimate();
+    Ok(template)
 }
 
 fn convert_interactions(interactions: Vec<RawInteraction>) -> Vec<InteractionData> {
@@ -1,2 +1,8 @@
+pub mod bmath;
+pub mod bnum;
+pub mod gas;
 pub mod helper;
+pub mod local_helper;
 
+#[cfg(test)]
+mod testing;
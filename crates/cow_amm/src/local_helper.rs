@@ -0,0 +1,449 @@
+//! Off-chain replica of the `BCowHelper` order generation.
+//!
+//! Computes the same template orders as [`crate::helper::Amm`] from a
+//! snapshot of the pool state instead of calling the helper contract.
+
+use {
+    crate::{
+        bmath,
+        bnum::{self, BNumError},
+        helper::TemplateOrder,
+    },
+    app_data::AppDataHash,
+    ethcontract::{Address, H160, U256},
+    hex_literal::hex,
+    model::{
+        DomainSeparator,
+        interaction::InteractionData,
+        order::{BuyTokenDestination, OrderData, OrderKind, SellTokenSource},
+        signature::{Signature, hashed_eip712_message},
+    },
+};
+
+/// App data of every order created by the helper.
+pub const APP_DATA: [u8; 32] =
+    hex!("362e5182440b52aa8fffe70a251550fbbcbca424740fe5a14f59bf0c1b06fe1d");
+
+/// Seconds after the current block an order of the helper stays valid.
+pub const MAX_ORDER_DURATION: u32 = 300;
+
+/// Selector of `BCoWPool.commit(bytes32)`.
+pub(crate) const COMMIT: [u8; 4] = hex!("f14fcbc8");
+
+// `GPv2Order.KIND_SELL` and `GPv2Order.BALANCE_ERC20`.
+const KIND_SELL: [u8; 32] =
+    hex!("f3b277728b3fee749481eb3e0b3b48980dbbab78658fc419025cb16eee346775");
+const BALANCE_ERC20: [u8; 32] =
+    hex!("5a28e9363bb942b639270062aa6bb295f434bcdfc42c97267bf003f272060dc9");
+
+/// Reasons the helper contract would revert for the same inputs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum HelperError {
+    #[error("InvalidToken")]
+    InvalidToken,
+    /// The helper only supports pools with exactly two tokens.
+    #[error("PoolDoesNotExist")]
+    PoolDoesNotExist,
+    #[error("expected a price for each of the 2 pool tokens")]
+    InvalidPrices,
+    #[error("valid to overflows")]
+    ValidToOverflow,
+    #[error(transparent)]
+    Math(#[from] BNumError),
+}
+
+/// State of a 2 token CoW AMM that the helper reads for creating orders.
+///
+/// The helper rejects pools with any other number of tokens, see
+/// [`PoolSnapshot::new`].
+#[derive(Clone, Debug)]
+pub struct PoolSnapshot {
+    pub address: Address,
+    /// Tokens in `getFinalTokens` order.
+    pub tokens: [Address; 2],
+    /// Balances of the pool, in `tokens` order.
+    pub balances: [U256; 2],
+    /// Denormalized weights, in `tokens` order.
+    pub weights: [U256; 2],
+    /// `SOLUTION_SETTLER_DOMAIN_SEPARATOR` of the pool.
+    pub domain_separator: DomainSeparator,
+}
+
+impl PoolSnapshot {
+    /// Snapshot of the pool at `address` with `tokens`, `balances` and
+    /// `weights` in `getFinalTokens` order. Pools that do not have exactly
+    /// two tokens are rejected like the helper rejects them.
+    pub fn new(
+        address: Address,
+        tokens: &[Address],
+        balances: &[U256],
+        weights: &[U256],
+        domain_separator: DomainSeparator,
+    ) -> Result<Self, HelperError> {
+        Ok(Self {
+            address,
+            tokens: tokens.try_into().map_err(|_| HelperError::PoolDoesNotExist)?,
+            balances: balances.try_into().map_err(|_| HelperError::PoolDoesNotExist)?,
+            weights: weights.try_into().map_err(|_| HelperError::PoolDoesNotExist)?,
+            domain_separator,
+        })
+    }
+
+    /// Replica of `BCowHelper.order`: the order that rebalances the pool to
+    /// `prices`, which are in `tokens` order.
+    pub fn order(&self, prices: &[U256], timestamp: u64) -> Result<TemplateOrder, HelperError> {
+        let [price_0, price_1] = match prices {
+            [price_0, price_1, ..] => [*price_0, *price_1],
+            _ => return Err(HelperError::InvalidPrices),
+        };
+
+        let total_weight = bnum::badd(self.weights[0], self.weights[1])?;
+        let normalized_0 = bnum::bdiv(self.weights[0], total_weight)?;
+        let normalized_1 = bnum::bdiv(self.weights[1], total_weight)?;
+        let reserve_0 = bnum::bmul(self.balances[0], normalized_1)?;
+        let reserve_1 = bnum::bmul(self.balances[1], normalized_0)?;
+        let value_0 = bnum::bmul(reserve_0, price_0)?;
+        let value_1 = bnum::bmul(reserve_1, price_1)?;
+
+        let (sell_index, sell_amount) = if value_1 > value_0 {
+            let target = bnum::bdiv(bnum::bmul(reserve_0, price_0)?, price_1)?;
+            (1, bnum::bsub(reserve_1, target)?)
+        } else {
+            let target = bnum::bdiv(bnum::bmul(reserve_1, price_1)?, price_0)?;
+            (0, bnum::bsub(reserve_0, target)?)
+        };
+        self.template_from_sell_amount(sell_index, sell_amount, timestamp)
+    }
+
+    /// Replica of `BCowHelper.orderFromSellAmount`, where `sell_token` is the
+    /// token the pool sells.
+    pub fn order_from_sell_amount(
+        &self,
+        sell_token: Address,
+        sell_amount: U256,
+        timestamp: u64,
+    ) -> Result<TemplateOrder, HelperError> {
+        let sell_index = self.index(sell_token)?;
+        self.template_from_sell_amount(sell_index, sell_amount, timestamp)
+    }
+
+    /// Replica of `BCowHelper.orderFromBuyAmount`, where `buy_token` is the
+    /// token the pool buys.
+    pub fn order_from_buy_amount(
+        &self,
+        buy_token: Address,
+        buy_amount: U256,
+        timestamp: u64,
+    ) -> Result<TemplateOrder, HelperError> {
+        let buy_index = self.index(buy_token)?;
+        let sell_index = 1 - buy_index;
+        let sell_amount = bmath::calc_out_given_in(
+            self.balances[buy_index],
+            self.weights[buy_index],
+            self.balances[sell_index],
+            self.weights[sell_index],
+            buy_amount,
+            U256::zero(),
+        )?;
+        self.template(sell_index, sell_amount, buy_amount, timestamp)
+    }
+
+    fn index(&self, token: Address) -> Result<usize, HelperError> {
+        self.tokens
+            .iter()
+            .position(|t| *t == token)
+            .ok_or(HelperError::InvalidToken)
+    }
+
+    /// The helper sizes the buy amount for the requested sell amount and then
+    /// recomputes the sell amount the pool accepts for it, which is why the
+    /// resulting sell amount is only close to the requested one.
+    fn template_from_sell_amount(
+        &self,
+        sell_index: usize,
+        sell_amount: U256,
+        timestamp: u64,
+    ) -> Result<TemplateOrder, HelperError> {
+        let buy_index = 1 - sell_index;
+        let buy_amount = bmath::calc_in_given_out(
+            self.balances[buy_index],
+            self.weights[buy_index],
+            self.balances[sell_index],
+            self.weights[sell_index],
+            sell_amount,
+            U256::zero(),
+        )?;
+        let sell_amount = bmath::calc_out_given_in(
+            self.balances[buy_index],
+            self.weights[buy_index],
+            self.balances[sell_index],
+            self.weights[sell_index],
+            buy_amount,
+            U256::zero(),
+        )?;
+        self.template(sell_index, sell_amount, buy_amount, timestamp)
+    }
+
+    fn template(
+        &self,
+        sell_index: usize,
+        sell_amount: U256,
+        buy_amount: U256,
+        timestamp: u64,
+    ) -> Result<TemplateOrder, HelperError> {
+        // The helper truncates the timestamp before adding the duration.
+        let valid_to = (timestamp as u32)
+            .checked_add(MAX_ORDER_DURATION)
+            .ok_or(HelperError::ValidToOverflow)?;
+        let order = OrderData {
+            sell_token: self.tokens[sell_index],
+            buy_token: self.tokens[1 - sell_index],
+            receiver: Some(H160::zero()),
+            sell_amount,
+            buy_amount,
+            valid_to,
+            app_data: AppDataHash(APP_DATA),
+            fee_amount: U256::zero(),
+            kind: OrderKind::Sell,
+            partially_fillable: true,
+            sell_token_balance: SellTokenSource::Erc20,
+            buy_token_balance: BuyTokenDestination::Erc20,
+        };
+
+        let hash = hashed_eip712_message(&self.domain_separator, &order.hash_struct());
+        let commitment = InteractionData {
+            target: self.address,
+            value: U256::zero(),
+            call_data: [COMMIT.as_slice(), &hash].concat(),
+        };
+        let signature = Signature::Eip1271(encode_order(&order));
+
+        let mut template = TemplateOrder {
+            order,
+            signature,
+            pre_interactions: vec![commitment],
+            post_interactions: vec![],
+            slippage_bps: None,
+            gas_estimate: 0,
+        };
+        template.update_gas_estimate();
+        Ok(template)
+    }
+}
+
+/// ABI encoding of a `GPv2Order.Data` created by the helper, which the pool
+/// expects as its EIP-1271 signature.
+fn encode_order(order: &OrderData) -> Vec<u8> {
+    let address = |address: Address| {
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(address.as_bytes());
+        word
+    };
+    let uint = |value: U256| {
+        let mut word = [0u8; 32];
+        value.to_big_endian(&mut word);
+        word
+    };
+    [
+        address(order.sell_token),
+        address(order.buy_token),
+        address(order.receiver.unwrap_or_default()),
+        uint(order.sell_amount),
+        uint(order.buy_amount),
+        uint(order.valid_to.into()),
+        order.app_data.0,
+        uint(order.fee_amount),
+        KIND_SELL,
+        uint(u8::from(order.partially_fillable).into()),
+        BALANCE_ERC20,
+        BALANCE_ERC20,
+    ]
+    .concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{bnum::BONE, helper::convert_orders_response},
+        contracts::BCowHelper,
+        ethcontract::tokens::Tokenize,
+    };
+
+    // Responses were recorded by running the deployed helper bytecode against
+    // a pool with these tokens and domain separator at `TIMESTAMP`.
+    const POOL: Address = H160([0x11; 20]);
+    const TOKEN_A: Address = H160([0xaa; 20]);
+    const TOKEN_B: Address = H160([0xbb; 20]);
+    const TIMESTAMP: u64 = 1_700_000_000;
+
+    fn bone(n: u64) -> U256 {
+        BONE * n
+    }
+
+    fn snapshot(balances: [U256; 2], weights: [U256; 2]) -> PoolSnapshot {
+        PoolSnapshot {
+            address: POOL,
+            tokens: [TOKEN_A, TOKEN_B],
+            balances,
+            weights,
+            domain_separator: DomainSeparator([0x42; 32]),
+        }
+    }
+
+    fn balanced() -> PoolSnapshot {
+        snapshot([bone(1000), bone(1000)], [BONE, BONE])
+    }
+
+    /// 80/20 pool with 1000 of token A and 500 of token B.
+    fn weighted() -> PoolSnapshot {
+        snapshot([bone(1000), bone(500)], [bone(4), BONE])
+    }
+
+    fn recorded(function: &str, response: &str) -> TemplateOrder {
+        let bytes = hex::decode(response.trim()).unwrap();
+        let tokens = BCowHelper::raw_contract()
+            .interface
+            .abi
+            .function(function)
+            .unwrap()
+            .decode_output(&bytes)
+            .unwrap();
+        let (order, pre_interactions, post_interactions, signature) =
+            Tokenize::from_token(ethcontract::common::abi::Token::Tuple(tokens)).unwrap();
+        convert_orders_response(order, signature, pre_interactions, post_interactions).unwrap()
+    }
+
+    fn assert_same(actual: TemplateOrder, expected: TemplateOrder) {
+        assert_eq!(actual.order, expected.order);
+        assert_eq!(actual.signature, expected.signature);
+        assert_eq!(actual.pre_interactions, expected.pre_interactions);
+        assert_eq!(actual.post_interactions, expected.post_interactions);
+        assert_eq!(actual.gas_estimate, expected.gas_estimate);
+    }
+
+    #[test]
+    fn replicates_order_from_sell_amount() {
+        let template = balanced()
+            .order_from_sell_amount(TOKEN_A, bone(10), TIMESTAMP)
+            .unwrap();
+        assert_eq!(template.order.sell_amount, bone(10));
+        assert_eq!(template.order.valid_to, 1_700_000_300);
+        assert_same(
+            template,
+            recorded(
+                "orderFromSellAmount",
+                include_str!("../testdata/sell_balanced.hex"),
+            ),
+        );
+
+        let template = weighted()
+            .order_from_sell_amount(TOKEN_B, bone(10), TIMESTAMP)
+            .unwrap();
+        assert_same(
+            template,
+            recorded(
+                "orderFromSellAmount",
+                include_str!("../testdata/sell_weighted.hex"),
+            ),
+        );
+    }
+
+    #[test]
+    fn replicates_order_from_buy_amount() {
+        let template = weighted()
+            .order_from_buy_amount(TOKEN_A, bone(25), TIMESTAMP)
+            .unwrap();
+        assert_eq!(template.order.buy_amount, bone(25));
+        assert_same(
+            template,
+            recorded(
+                "orderFromBuyAmount",
+                include_str!("../testdata/buy_weighted.hex"),
+            ),
+        );
+    }
+
+    #[test]
+    fn replicates_order() {
+        let template = balanced().order(&[BONE, bone(2)], TIMESTAMP).unwrap();
+        assert_eq!(template.order.sell_token, TOKEN_B);
+        assert_same(
+            template,
+            recorded("order", include_str!("../testdata/order_balanced.hex")),
+        );
+
+        let template = weighted().order(&[bone(3), BONE], TIMESTAMP).unwrap();
+        assert_eq!(template.order.sell_token, TOKEN_A);
+        assert_same(
+            template,
+            recorded("order", include_str!("../testdata/order_weighted.hex")),
+        );
+    }
+
+    #[test]
+    fn only_supports_two_token_pools() {
+        let pool = PoolSnapshot::new(
+            POOL,
+            &[TOKEN_A, TOKEN_B],
+            &[bone(1000), bone(500)],
+            &[bone(4), BONE],
+            DomainSeparator([0x42; 32]),
+        )
+        .unwrap();
+        assert_same(
+            pool.order_from_buy_amount(TOKEN_A, bone(25), TIMESTAMP).unwrap(),
+            weighted()
+                .order_from_buy_amount(TOKEN_A, bone(25), TIMESTAMP)
+                .unwrap(),
+        );
+
+        let token_c = H160([0xcc; 20]);
+        assert_eq!(
+            PoolSnapshot::new(
+                POOL,
+                &[TOKEN_A, TOKEN_B, token_c],
+                &[bone(1000), bone(1000), bone(1000)],
+                &[BONE, BONE, BONE],
+                DomainSeparator([0x42; 32]),
+            )
+            .err(),
+            Some(HelperError::PoolDoesNotExist)
+        );
+        assert_eq!(
+            PoolSnapshot::new(
+                POOL,
+                &[TOKEN_A],
+                &[bone(1000)],
+                &[BONE],
+                DomainSeparator([0x42; 32]),
+            )
+            .err(),
+            Some(HelperError::PoolDoesNotExist)
+        );
+    }
+
+    #[test]
+    fn reverts_like_the_helper() {
+        let pool = balanced();
+        assert_eq!(
+            pool.order_from_sell_amount(H160([0xcc; 20]), bone(10), TIMESTAMP)
+                .err(),
+            Some(HelperError::InvalidToken)
+        );
+        assert_eq!(
+            pool.order_from_sell_amount(TOKEN_A, bone(1000), TIMESTAMP)
+                .err(),
+            Some(HelperError::Math(BNumError::DivZero))
+        );
+        assert_eq!(
+            pool.order(&[BONE], TIMESTAMP).err(),
+            Some(HelperError::InvalidPrices)
+        );
+        assert_eq!(
+            pool.order_from_sell_amount(TOKEN_A, bone(10), u64::from(u32::MAX))
+                .err(),
+            Some(HelperError::ValidToOverflow)
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Executes the vendored `BCowHelper` bytecode in revm, so that the ports of
+//! the contract math can be tested against the contract itself.
+
+use {
+    contracts::{BCowHelper, cow_amm_revert::CowAmmRevert},
+    ethcontract::{U256, common::abi::Token},
+    revm::{
+        Context, ExecuteCommitEvm, MainBuilder, MainContext,
+        bytecode::Bytecode,
+        context::TxEnv,
+        context_interface::result::{ExecutionResult, Output},
+        database::{CacheDB, EmptyDB},
+        primitives::{Address, TxKind},
+        state::AccountInfo,
+    },
+};
+
+const HELPER: Address = Address::repeat_byte(0x77);
+
+/// Gas a call may use. Some inputs make `bpowApprox` add terms until it runs
+/// out of any reasonable amount of gas.
+const GAS_LIMIT: u64 = 30_000_000;
+
+/// Calls a `BCowHelper` function that returns a single `uint256`.
+///
+/// Reverts are reported with the name of the custom error, which is what the
+/// `BNumError` variants display as. Returns `None` if the call ran out of gas.
+pub fn call_uint(function: &str, args: &[U256]) -> Option<Result<U256, String>> {
+    let function = BCowHelper::raw_contract()
+        .interface
+        .abi
+        .function(function)
+        .unwrap();
+    let args: Vec<_> = args.iter().copied().map(Token::Uint).collect();
+    let calldata = function.encode_input(&args).unwrap();
+
+    match execute(calldata) {
+        ExecutionResult::Success {
+            output: Output::Call(output),
+            ..
+        } => Some(Ok(U256::from_big_endian(&output))),
+        ExecutionResult::Revert { output, .. } => Some(Err(CowAmmRevert::decode(&output)
+            .map(|revert| revert.name().to_owned())
+            .unwrap_or_else(|| hex::encode(&output)))),
+        ExecutionResult::Halt { .. } => None,
+        result => panic!("unexpected result {result:?}"),
+    }
+}
+
+fn execute(calldata: Vec<u8>) -> ExecutionResult {
+    let code = BCowHelper::raw_contract()
+        .deployed_bytecode
+        .to_bytes()
+        .unwrap();
+    let bytecode = Bytecode::new_raw(code.0.into());
+    let mut db = CacheDB::<EmptyDB>::default();
+    db.insert_account_info(
+        HELPER,
+        AccountInfo {
+            code_hash: bytecode.hash_slow(),
+            code: Some(bytecode),
+            ..Default::default()
+        },
+    );
+
+    let mut evm = Context::mainnet().with_db(db).build_mainnet();
+    evm.transact_commit(TxEnv {
+        kind: TxKind::Call(HELPER),
+        data: calldata.into(),
+        gas_limit: GAS_LIMIT,
+        ..Default::default()
+    })
+    .unwrap()
+}
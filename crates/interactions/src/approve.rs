@@ -0,0 +1,49 @@
+use {
+    contracts::ERC20,
+    ethcontract::{Address, tokens::Bytes},
+    primitive_types::U256,
+    shared::interaction::{EncodedInteraction, Interaction},
+};
+
+/// Approves `spender` to transfer `amount` of the settlement contract's
+/// `token`.
+#[derive(Clone, Debug)]
+pub struct Erc20ApproveInteraction {
+    pub token: ERC20,
+    pub spender: Address,
+    pub amount: U256,
+}
+
+impl Interaction for Erc20ApproveInteraction {
+    fn encode(&self) -> EncodedInteraction {
+        let method = self.token.approve(self.spender, self.amount);
+        let calldata = method.tx.data.expect("no calldata").0;
+        (self.token.address(), 0.into(), Bytes(calldata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, contracts::dummy_contract, hex_literal::hex, primitive_types::H160};
+
+    #[test]
+    fn encode_approve() {
+        let interaction = Erc20ApproveInteraction {
+            token: dummy_contract!(ERC20, [0x01; 20]),
+            spender: H160([0x02; 20]),
+            amount: 1_000.into(),
+        };
+
+        let (to, value, data) = interaction.encode();
+        assert_eq!(to, H160([0x01; 20]));
+        assert_eq!(value, U256::zero());
+        assert_eq!(
+            data.0,
+            hex!(
+                "095ea7b3"
+                "0000000000000000000000000202020202020202020202020202020202020202"
+                "00000000000000000000000000000000000000000000000000000000000003e8"
+            )
+        );
+    }
+}
@@ -0,0 +1,608 @@
+//! Decoding of `GPv2Settlement.settle` calldata, the inverse of
+//! [`crate::settlement`].
+//!
+//! Meant for debugging settlements: the decoded trades recover the signed
+//! orders and the interactions are labelled with the CoW AMM, WETH and hook
+//! calls they make.
+
+use {
+    crate::{
+        errors::DecodeError,
+        settlement::{EncodedInteraction, EncodedTrade},
+    },
+    app_data::AppDataHash,
+    contracts::{BCowPool, GPv2Settlement, HooksTrampoline, WETH9},
+    ethcontract::{
+        Address, H256, U256,
+        common::{Contract, abi::Token},
+        tokens::{Bytes, Tokenize},
+    },
+    model::{
+        interaction::InteractionData,
+        order::{BuyTokenDestination, OrderData, OrderKind, SellTokenSource},
+        signature::{EcdsaSignature, EcdsaSigningScheme, Signature},
+    },
+    std::fmt::{self, Display, Formatter},
+};
+
+/// Arguments of a `settle` call.
+#[derive(Clone, Debug)]
+pub struct DecodedSettlement {
+    pub tokens: Vec<Address>,
+    pub clearing_prices: Vec<U256>,
+    pub trades: Vec<DecodedTrade>,
+    /// Interactions executed before, during and after the trades.
+    pub interactions: [Vec<DecodedInteraction>; 3],
+}
+
+/// Trade of a settlement with the order that was signed for it.
+#[derive(Clone, Debug)]
+pub struct DecodedTrade {
+    /// The receiver is `None` if the order pays out to its owner.
+    pub order: OrderData,
+    pub signature: Signature,
+    /// Owner of EIP-1271 and pre-signed orders. ECDSA signed orders only
+    /// encode the signature, so their owner has to be recovered from it.
+    pub owner: Option<Address>,
+    pub executed_amount: U256,
+}
+
+/// Interaction of a settlement together with the call it makes, if known.
+#[derive(Clone, Debug)]
+pub struct DecodedInteraction {
+    pub interaction: InteractionData,
+    pub call: Call,
+}
+
+/// Calls that settlements with CoW AMMs commonly make.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Call {
+    /// `BCoWPool.commit`
+    Commit(H256),
+    /// `BCoWPool.joinPool`
+    JoinPool {
+        pool_amount_out: U256,
+        max_amounts_in: Vec<U256>,
+    },
+    /// `BCoWPool.exitPool`
+    ExitPool {
+        pool_amount_in: U256,
+        min_amounts_out: Vec<U256>,
+    },
+    /// `WETH9.deposit`
+    WethDeposit,
+    /// `WETH9.withdraw`
+    WethWithdraw(U256),
+    /// `HooksTrampoline.execute`
+    Hooks(Vec<Hook>),
+    /// Any other call.
+    Unknown,
+}
+
+/// Hook executed by the `HooksTrampoline`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Hook {
+    pub target: Address,
+    pub call_data: Vec<u8>,
+    pub gas_limit: U256,
+}
+
+type SettleArguments = (
+    Vec<Address>,
+    Vec<U256>,
+    Vec<EncodedTrade>,
+    [Vec<EncodedInteraction>; 3],
+);
+
+/// Decodes `settle` calldata. Trailing bytes, like the auction id the
+/// driver appends to settlement transactions, are ignored.
+pub fn decode_settlement(calldata: &[u8]) -> Result<DecodedSettlement, DecodeError> {
+    let (tokens, clearing_prices, trades, interactions): SettleArguments =
+        decode_call(GPv2Settlement::raw_contract(), "settle", calldata)
+            .ok_or(DecodeError::NotSettle)??;
+
+    let trades = trades
+        .into_iter()
+        .enumerate()
+        .map(|(index, trade)| decode_trade(index, &tokens, trade))
+        .collect::<Result<_, _>>()?;
+    let interactions = interactions.map(|interactions| {
+        interactions
+            .into_iter()
+            .map(|(target, value, Bytes(call_data))| {
+                let interaction = InteractionData {
+                    target,
+                    value,
+                    call_data,
+                };
+                DecodedInteraction {
+                    call: decode_interaction(&interaction.call_data),
+                    interaction,
+                }
+            })
+            .collect()
+    });
+
+    Ok(DecodedSettlement {
+        tokens,
+        clearing_prices,
+        trades,
+        interactions,
+    })
+}
+
+/// Decodes the arguments of `function` if `calldata` calls it. Returns
+/// `None` for calls of other functions.
+fn decode_call<T: Tokenize>(
+    contract: &Contract,
+    function: &str,
+    calldata: &[u8],
+) -> Option<Result<T, DecodeError>> {
+    let function = contract
+        .interface
+        .abi
+        .function(function)
+        .expect("function exists");
+    let data = calldata.strip_prefix(&function.short_signature())?;
+    Some(
+        function
+            .decode_input(data)
+            .map_err(|err| DecodeError::Abi(err.to_string()))
+            .and_then(|tokens| {
+                T::from_token(Token::Tuple(tokens)).map_err(|err| DecodeError::Abi(err.to_string()))
+            }),
+    )
+}
+
+fn decode_trade(
+    index: usize,
+    tokens: &[Address],
+    trade: EncodedTrade,
+) -> Result<DecodedTrade, DecodeError> {
+    let (
+        sell_token_index,
+        buy_token_index,
+        receiver,
+        sell_amount,
+        buy_amount,
+        valid_to,
+        Bytes(app_data),
+        fee_amount,
+        flags,
+        executed_amount,
+        Bytes(signature),
+    ) = trade;
+    let token = |token_index: U256| {
+        usize::try_from(token_index)
+            .ok()
+            .and_then(|token_index| tokens.get(token_index).copied())
+            .ok_or(DecodeError::InvalidTokenIndex {
+                trade: index,
+                token_index,
+            })
+    };
+
+    let flags = flags.low_u32();
+    let order = OrderData {
+        sell_token: token(sell_token_index)?,
+        buy_token: token(buy_token_index)?,
+        receiver: Some(receiver).filter(|receiver| !receiver.is_zero()),
+        sell_amount,
+        buy_amount,
+        valid_to,
+        app_data: AppDataHash(app_data),
+        fee_amount,
+        kind: if flags & 1 == 0 {
+            OrderKind::Sell
+        } else {
+            OrderKind::Buy
+        },
+        partially_fillable: flags & (1 << 1) != 0,
+        sell_token_balance: match (flags >> 2) & 0b11 {
+            0b10 => SellTokenSource::External,
+            0b11 => SellTokenSource::Internal,
+            _ => SellTokenSource::Erc20,
+        },
+        buy_token_balance: match (flags >> 4) & 1 {
+            0 => BuyTokenDestination::Erc20,
+            _ => BuyTokenDestination::Internal,
+        },
+    };
+    let (signature, owner) = decode_signature((flags >> 5) & 0b11, &signature)
+        .ok_or(DecodeError::InvalidSignature(index))?;
+
+    Ok(DecodedTrade {
+        order,
+        signature,
+        owner,
+        executed_amount,
+    })
+}
+
+/// Splits the encoded signature of a trade into the signature and the owner
+/// it encodes, if any.
+fn decode_signature(scheme: u32, signature: &[u8]) -> Option<(Signature, Option<Address>)> {
+    let ecdsa = |scheme| {
+        let signature: &[u8; 65] = signature.try_into().ok()?;
+        let ecdsa = EcdsaSignature {
+            r: H256::from_slice(&signature[..32]),
+            s: H256::from_slice(&signature[32..64]),
+            v: signature[64],
+        };
+        Some((ecdsa.to_signature(scheme), None))
+    };
+    match scheme {
+        0 => ecdsa(EcdsaSigningScheme::Eip712),
+        1 => ecdsa(EcdsaSigningScheme::EthSign),
+        2 if signature.len() >= 20 => {
+            let (owner, data) = signature.split_at(20);
+            Some((
+                Signature::Eip1271(data.to_vec()),
+                Some(Address::from_slice(owner)),
+            ))
+        }
+        3 if signature.len() == 20 => {
+            Some((Signature::PreSign, Some(Address::from_slice(signature))))
+        }
+        _ => None,
+    }
+}
+
+/// Identifies the call by its selector and decodes its arguments.
+pub(crate) fn decode_interaction(call_data: &[u8]) -> Call {
+    let pool = BCowPool::raw_contract();
+    let weth = WETH9::raw_contract();
+    let decoded = None
+        .or_else(|| {
+            decode_call(pool, "commit", call_data)
+                .map(|args| args.map(|(Bytes(hash),)| Call::Commit(H256(hash))))
+        })
+        .or_else(|| {
+            decode_call(pool, "joinPool", call_data).map(|args| {
+                args.map(|(pool_amount_out, max_amounts_in)| Call::JoinPool {
+                    pool_amount_out,
+                    max_amounts_in,
+                })
+            })
+        })
+        .or_else(|| {
+            decode_call(pool, "exitPool", call_data).map(|args| {
+                args.map(|(pool_amount_in, min_amounts_out)| Call::ExitPool {
+                    pool_amount_in,
+                    min_amounts_out,
+                })
+            })
+        })
+        .or_else(|| {
+            decode_call(weth, "deposit", call_data).map(|args| args.map(|()| Call::WethDeposit))
+        })
+        .or_else(|| {
+            decode_call(weth, "withdraw", call_data)
+                .map(|args| args.map(|(amount,)| Call::WethWithdraw(amount)))
+        })
+        .or_else(|| {
+            decode_call(HooksTrampoline::raw_contract(), "execute", call_data).map(|args| {
+                args.map(|(hooks,): (Vec<(Address, Bytes<Vec<u8>>, U256)>,)| {
+                    Call::Hooks(
+                        hooks
+                            .into_iter()
+                            .map(|(target, Bytes(call_data), gas_limit)| Hook {
+                                target,
+                                call_data,
+                                gas_limit,
+                            })
+                            .collect(),
+                    )
+                })
+            })
+        });
+    // Calls that match a selector but not its arguments are not what they
+    // seem to be.
+    match decoded {
+        Some(Ok(call)) => call,
+        _ => Call::Unknown,
+    }
+}
+
+impl Display for DecodedSettlement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "tokens:")?;
+        for (index, (token, price)) in self.tokens.iter().zip(&self.clearing_prices).enumerate() {
+            writeln!(f, "  [{index}] {token:?} price {price}")?;
+        }
+        writeln!(f, "trades:")?;
+        for (index, trade) in self.trades.iter().enumerate() {
+            writeln!(f, "  [{index}] {trade}")?;
+        }
+        for (phase, interactions) in ["pre", "intra", "post"].iter().zip(&self.interactions) {
+            writeln!(f, "{phase}-interactions:")?;
+            for interaction in interactions {
+                writeln!(f, "  {interaction}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for DecodedTrade {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let order = &self.order;
+        let kind = match order.kind {
+            OrderKind::Sell => "sell",
+            OrderKind::Buy => "buy",
+        };
+        write!(
+            f,
+            "{kind} {} {:?} for {} {:?}, executed {}",
+            order.sell_amount,
+            order.sell_token,
+            order.buy_amount,
+            order.buy_token,
+            self.executed_amount,
+        )?;
+        if order.partially_fillable {
+            write!(f, " (partially fillable)")?;
+        }
+        write!(f, ", valid to {}", order.valid_to)?;
+        if let Some(owner) = self.owner {
+            write!(f, ", owner {owner:?}")?;
+        }
+        let scheme = match self.signature {
+            Signature::Eip712(_) => "eip712",
+            Signature::EthSign(_) => "ethsign",
+            Signature::Eip1271(_) => "eip1271",
+            Signature::PreSign => "presign",
+        };
+        write!(f, ", {scheme}")
+    }
+}
+
+impl Display for DecodedInteraction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let interaction = &self.interaction;
+        write!(f, "{:?}", interaction.target)?;
+        if !interaction.value.is_zero() {
+            write!(f, " value {}", interaction.value)?;
+        }
+        match &self.call {
+            Call::Commit(hash) => write!(f, " commit({hash:?})"),
+            Call::JoinPool {
+                pool_amount_out,
+                max_amounts_in,
+            } => write!(f, " joinPool({pool_amount_out}, {max_amounts_in:?})"),
+            Call::ExitPool {
+                pool_amount_in,
+                min_amounts_out,
+            } => write!(f, " exitPool({pool_amount_in}, {min_amounts_out:?})"),
+            Call::WethDeposit => write!(f, " deposit()"),
+            Call::WethWithdraw(amount) => write!(f, " withdraw({amount})"),
+            Call::Hooks(hooks) => {
+                write!(f, " execute(")?;
+                for (index, hook) in hooks.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(
+                        f,
+                        "{:?} 0x{} gas {}",
+                        hook.target,
+                        hex::encode(&hook.call_data),
+                        hook.gas_limit
+                    )?;
+                }
+                write!(f, ")")
+            }
+            Call::Unknown => write!(f, " 0x{}", hex::encode(&interaction.call_data)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::settlement::{InteractionPhase, Settlement, Trade},
+        ethcontract::H160,
+        maplit::hashmap,
+    };
+
+    const POOL: Address = H160([0x11; 20]);
+    const USER: Address = H160([0x22; 20]);
+    const WETH: Address = H160([0x33; 20]);
+    const TOKEN_A: Address = H160([0xaa; 20]);
+    const TOKEN_B: Address = H160([0xbb; 20]);
+
+    fn interaction(target: Address, call_data: Vec<u8>) -> InteractionData {
+        InteractionData {
+            target,
+            value: U256::zero(),
+            call_data,
+        }
+    }
+
+    fn encode<T: Tokenize>(contract: &Contract, function: &str, args: T) -> Vec<u8> {
+        let Token::Tuple(tokens) = args.into_token() else {
+            unreachable!("arguments are a tuple");
+        };
+        contract
+            .interface
+            .abi
+            .function(function)
+            .unwrap()
+            .encode_input(&tokens)
+            .unwrap()
+    }
+
+    fn settlement() -> Settlement {
+        let pool = BCowPool::raw_contract();
+        let mut settlement = Settlement::new(hashmap! {
+            TOKEN_A => 2.into(),
+            TOKEN_B => 3.into(),
+        });
+        settlement.add_trade(Trade {
+            order: OrderData {
+                sell_token: TOKEN_A,
+                buy_token: TOKEN_B,
+                receiver: Some(H160::zero()),
+                sell_amount: 30.into(),
+                buy_amount: 20.into(),
+                valid_to: 1_700_000_300,
+                app_data: AppDataHash([0x44; 32]),
+                kind: OrderKind::Sell,
+                partially_fillable: true,
+                ..Default::default()
+            },
+            owner: POOL,
+            signature: Signature::Eip1271(vec![0xee; 8]),
+            executed_amount: 30.into(),
+        });
+        settlement.add_trade(Trade {
+            order: OrderData {
+                sell_token: TOKEN_B,
+                buy_token: TOKEN_A,
+                receiver: Some(USER),
+                sell_amount: 20.into(),
+                buy_amount: 30.into(),
+                kind: OrderKind::Buy,
+                sell_token_balance: SellTokenSource::Internal,
+                ..Default::default()
+            },
+            owner: USER,
+            signature: EcdsaSignature {
+                r: H256([1; 32]),
+                s: H256([2; 32]),
+                v: 27,
+            }
+            .to_signature(EcdsaSigningScheme::EthSign),
+            executed_amount: 30.into(),
+        });
+        settlement.add_interaction(
+            InteractionPhase::Pre,
+            interaction(POOL, encode(pool, "commit", (Bytes([0x55; 32]),))),
+        );
+        settlement.add_interaction(
+            InteractionPhase::Pre,
+            interaction(
+                POOL,
+                encode(
+                    pool,
+                    "exitPool",
+                    (U256::from(7), vec![U256::from(1), U256::from(2)]),
+                ),
+            ),
+        );
+        settlement.add_interaction(
+            InteractionPhase::Intra,
+            interaction(
+                WETH,
+                encode(WETH9::raw_contract(), "withdraw", (U256::from(5),)),
+            ),
+        );
+        settlement.add_interaction(
+            InteractionPhase::Post,
+            interaction(
+                H160([0x44; 20]),
+                encode(
+                    HooksTrampoline::raw_contract(),
+                    "execute",
+                    (vec![(POOL, Bytes(vec![0xab]), U256::from(100_000))],),
+                ),
+            ),
+        );
+        settlement.add_interaction(InteractionPhase::Post, interaction(USER, vec![0xde, 0xad]));
+        settlement
+    }
+
+    #[test]
+    fn decodes_encoded_settlement() {
+        let mut calldata = settlement().encode().unwrap().calldata();
+        // Auction id appended by the driver.
+        calldata.extend([0; 8]);
+        let decoded = decode_settlement(&calldata).unwrap();
+
+        assert_eq!(decoded.tokens, vec![TOKEN_A, TOKEN_B]);
+        assert_eq!(decoded.clearing_prices, vec![2.into(), 3.into()]);
+
+        let pool_trade = &decoded.trades[0];
+        assert_eq!(pool_trade.owner, Some(POOL));
+        assert_eq!(pool_trade.signature, Signature::Eip1271(vec![0xee; 8]));
+        assert_eq!(pool_trade.order.receiver, None);
+        assert_eq!(pool_trade.order.app_data, AppDataHash([0x44; 32]));
+        assert!(pool_trade.order.partially_fillable);
+        assert_eq!(pool_trade.executed_amount, 30.into());
+
+        let user_trade = &decoded.trades[1];
+        assert_eq!(user_trade.owner, None);
+        assert!(matches!(user_trade.signature, Signature::EthSign(_)));
+        assert_eq!(user_trade.order.kind, OrderKind::Buy);
+        assert_eq!(user_trade.order.receiver, Some(USER));
+        assert_eq!(
+            user_trade.order.sell_token_balance,
+            SellTokenSource::Internal
+        );
+
+        let calls = decoded
+            .interactions
+            .iter()
+            .map(|phase| phase.iter().map(|i| i.call.clone()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            calls,
+            vec![
+                vec![
+                    Call::Commit(H256([0x55; 32])),
+                    Call::ExitPool {
+                        pool_amount_in: 7.into(),
+                        min_amounts_out: vec![1.into(), 2.into()],
+                    },
+                ],
+                vec![Call::WethWithdraw(5.into())],
+                vec![
+                    Call::Hooks(vec![Hook {
+                        target: POOL,
+                        call_data: vec![0xab],
+                        gas_limit: 100_000.into(),
+                    }]),
+                    Call::Unknown,
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn prints_settlement() {
+        let calldata = settlement().encode().unwrap().calldata();
+        let printed = decode_settlement(&calldata).unwrap().to_string();
+
+        assert!(printed.contains(&format!("[0] {TOKEN_A:?} price 2")));
+        assert!(printed.contains(&format!(
+            "sell 30 {TOKEN_A:?} for 20 {TOKEN_B:?}, executed 30 (partially fillable), valid to \
+             1700000300, owner {POOL:?}, eip1271"
+        )));
+        assert!(printed.contains(&format!("{POOL:?} exitPool(7, [1, 2])")));
+        assert!(printed.contains(&format!("{WETH:?} withdraw(5)")));
+        assert!(printed.contains(&format!("{USER:?} 0xdead")));
+    }
+
+    #[test]
+    fn rejects_invalid_calldata() {
+        assert!(matches!(
+            decode_settlement(&[0xde, 0xad, 0xbe, 0xef]),
+            Err(DecodeError::NotSettle)
+        ));
+        assert!(matches!(
+            decode_settlement(&hex_literal::hex!("13d79a0b")),
+            Err(DecodeError::Abi(_))
+        ));
+
+        let mut encoded = settlement().encode().unwrap();
+        encoded.trades[1].1 = 5.into();
+        assert!(matches!(
+            decode_settlement(&encoded.calldata()),
+            Err(DecodeError::InvalidTokenIndex {
+                trade: 1,
+                token_index,
+            }) if token_index == 5.into()
+        ));
+    }
+}
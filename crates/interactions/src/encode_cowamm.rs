@@ -1,275 +1,1140 @@
 use {
     std::{
-        any::Any,
+        fmt::{self, Debug, Formatter},
         sync::Arc
     },
-    hex,
-    anyhow::{Context, Result},
-    ethcontract::{Address, U256},
-    contracts::BCowPool,
+    ethcontract::{
+        dyns::{DynTransport, DynWeb3},
+        web3::{BatchTransport, Transport, Web3},
+        Address, U256,
+    },
+    contracts::{BCowPool, ERC20, GPv2Settlement, WETH9},
     model::{
         interaction::InteractionData,
+        order::{OrderKind, BUY_ETH_ADDRESS},
+        DomainSeparator,
+    },
+    shared::{
+        interaction::Interaction,
+        signature_validator::SignatureValidating,
     },
-    cow_amm::helper::{
-        TemplateOrder, Amm
+    cow_amm::{
+        bnum,
+        helper::{TemplateOrder, Amm},
+        local_helper::{PoolSnapshot, MAX_ORDER_DURATION},
     },
     crate::{
+        approve::Erc20ApproveInteraction,
+        errors::EncodeError,
         join_pool::JoinPoolInteraction, 
-        exit_pool::ExitPoolInteraction
+        exit_pool::ExitPoolInteraction,
+        pool_swap::{SwapExactAmountInInteraction, SwapExactAmountOutInteraction},
+        sizing,
+        weth::{WethDepositInteraction, WethWithdrawInteraction},
     },
     num_bigint::BigUint,
-    contracts::{
-        contract, BCowHelper
-    },
+    contracts::BCowHelper,
     tycho_simulation::{
         tycho_common::Bytes,
-        evm::protocol::{
-            // u256_num::biguint_to_u256,
-            cowamm::state::CowAMMState
-        },
         foundry_evm::revm::primitives::U256 as AlloyU256,
     },
 };
 
-pub trait PoolState {
-   fn as_any(&self) -> &dyn std::any::Any;
+pub use crate::pool_state::PoolState;
+
+/// Encodes CoW AMM swaps, pool joins and pool exits into template orders.
+///
+/// The encoder is constructed once for a node connection and can be reused
+/// across calls, so callers decide which node (private RPC, local fork, ...)
+/// the helper contract is queried through.
+#[derive(Clone, Debug)]
+pub struct CowAmmEncoder {
+    web3: DynWeb3,
+    chain_id: u64,
+    helper: BCowHelper,
+    signature_validation: Option<SignatureValidation>,
+    slippage_bps: u32,
+    weth: Option<WETH9>,
+    allowance_owner: Option<Address>,
+}
+
+/// Slippage tolerance of 100%, in basis points.
+const MAX_SLIPPAGE_BPS: u32 = 10_000;
+
+/// Verifies the signatures of encoded templates to protect against buggy
+/// helper contracts.
+#[derive(Clone)]
+struct SignatureValidation {
+    validator: Arc<dyn SignatureValidating>,
+    domain_separator: DomainSeparator,
+}
+
+impl Debug for SignatureValidation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignatureValidation")
+            .field("domain_separator", &self.domain_separator)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Template of one of the encoded cases together with the amounts of
+/// `token_in` the settlement contract pays and of `token_out` it receives at
+/// least, which native ETH is wrapped and unwrapped for.
+struct Encoded {
+    template: TemplateOrder,
+    paid: U256,
+    received: U256,
+}
 
-   fn as_any_mut(&mut self) -> &mut dyn Any;
+impl Encoded {
+    /// A plain trade with the pool, whose order is all that is paid and
+    /// received.
+    fn swap(template: TemplateOrder) -> Self {
+        let paid = template.order.buy_amount;
+        let received = template.order.sell_amount;
+        Self {
+            template,
+            paid,
+            received,
+        }
+    }
 }
 
-impl PoolState for CowAMMState {
-    fn as_any(&self) -> &dyn std::any::Any {
+/// The chain the encoder was constructed for has no known `BCowHelper`
+/// deployment, so the helper address has to be provided explicitly.
+#[derive(Debug, thiserror::Error)]
+#[error("missing BCowHelper deployment for chain {0}")]
+pub struct MissingHelperDeployment(pub u64);
+
+impl CowAmmEncoder {
+    /// Creates an encoder that queries the `BCowHelper` deployed on
+    /// `chain_id` through the provided `web3` transport.
+    pub fn new<T>(web3: &Web3<T>, chain_id: u64) -> Result<Self, MissingHelperDeployment>
+    where
+        T: Transport + BatchTransport + Send + Sync + 'static,
+        T::Out: Send + 'static,
+        T::Batch: Send + 'static,
+    {
+        let helper = contracts::deployment(BCowHelper::raw_contract(), chain_id)
+            .map_err(|_| MissingHelperDeployment(chain_id))?
+            .address;
+        Ok(Self::with_helper(web3, chain_id, helper))
+    }
+
+    /// Creates an encoder that queries the helper contract at `helper`
+    /// instead of the one registered for `chain_id`. Useful for local forks
+    /// and chains without a known deployment.
+    pub fn with_helper<T>(web3: &Web3<T>, chain_id: u64, helper: Address) -> Self
+    where
+        T: Transport + BatchTransport + Send + Sync + 'static,
+        T::Out: Send + 'static,
+        T::Batch: Send + 'static,
+    {
+        let web3 = Web3::new(DynTransport::new(web3.transport().clone()));
+        let helper = BCowHelper::at(&web3, helper);
+        let weth = contracts::deployment(WETH9::raw_contract(), chain_id)
+            .ok()
+            .map(|deployment| WETH9::at(&web3, deployment.address));
+        Self {
+            web3,
+            chain_id,
+            helper,
+            signature_validation: None,
+            slippage_bps: 0,
+            weth,
+            allowance_owner: None,
+        }
+    }
+
+    /// Sets the WETH contract that native ETH is wrapped into. Defaults to
+    /// the WETH deployed on the encoder's chain, if there is one.
+    pub fn with_weth(mut self, weth: Address) -> Self {
+        self.weth = Some(WETH9::at(&self.web3, weth));
         self
     }
 
-    fn as_any_mut(&mut self) -> &mut dyn Any {
+    /// Sets the slippage tolerance in basis points for pool joins and exits.
+    ///
+    /// The maximum amounts a join may pull from the settlement are raised
+    /// and the minimum amounts an exit has to return are lowered by this
+    /// tolerance, so the interactions survive small state changes between
+    /// encoding and settlement. Defaults to 0, which uses the exact amounts.
+    /// Tolerances above 10000 bps are clamped to 100%.
+    pub fn with_slippage_bps(mut self, bps: u32) -> Self {
+        self.slippage_bps = bps.min(MAX_SLIPPAGE_BPS);
         self
     }
-}
 
-/// Encodes a CowAMM swap for the three possible cases:
-/// 1. Token A -> Token B (normal swap)
-/// 2. Token A -> LP Token (join pool: swap + add liquidity)
-/// 3. LP Token -> Token A (exit pool: remove liquidity + swap)
-///
-/// # Arguments
-/// * `amount_in` - Amount of token_in to sell/swap
-/// * `token_in` - Address of the input token
-/// * `token_out` - Address of the output token
-/// * `pool_state` - Current state of the CowAMM pool for off-chain calculations
-///
-/// # Returns
-/// A `TemplateOrder` with the main order and appropriate pre/post interactions
-pub async fn encode_cowamm(
-    amount_in: BigUint,
-    token_in: Bytes,
-    token_out: Bytes,
-    pool_state: Arc<dyn PoolState>,
-) -> Result<TemplateOrder> {
-    let helper_addr: Address = Address::from_slice(&hex::decode("3FF0041A614A9E6Bf392cbB961C97DA214E9CB31").unwrap());
-
-    let binding = contract!(BCowHelper, helper_addr);
-
-    let amm = Amm::new(helper_addr, &binding).await.unwrap(); 
-
-    let pool_state = pool_state
-            .as_any()
-            .downcast_ref::<CowAMMState>()
-            .unwrap();
-    // Convert BigUint to U256
-    let amount_in_u256 = biguint_to_u256(&amount_in); 
-    
-    // Convert Bytes to Address
-    let token_in_addr = bytes_to_address(&token_in)?;
-    let token_out_addr = bytes_to_address(&token_out)?;
-    
-    // Get pool address and LP token address
-    let pool_address = bytes_to_address(&pool_state.address)?;
-    let lp_token_addr = bytes_to_address(&pool_state.lp_token)?;
-    
-    // Determine which case we're handling
-    let is_lp_in = token_in == pool_state.lp_token;
-    let is_lp_out = token_out == pool_state.lp_token;
-    
-    match (is_lp_in, is_lp_out) {
-        // Case 1: Normal Token A -> Token B swap
-        (false, false) => {
-            encode_normal_swap(
-                amount_in_u256,
-                token_in_addr,
-                token_out_addr,
-                &amm,
-            ).await
+    /// Validates the signature of every encoded template against the pool
+    /// before returning it. The check runs the template's pre-interactions,
+    /// including an appended pool exit, before verifying the signature.
+    pub fn with_signature_validation(
+        mut self,
+        validator: Arc<dyn SignatureValidating>,
+        domain_separator: DomainSeparator,
+    ) -> Self {
+        self.signature_validation = Some(SignatureValidation {
+            validator,
+            domain_separator,
+        });
+        self
+    }
+
+    /// Checks the allowances the settlement contract at `settlement` gave a
+    /// pool before joining it and approves the pool to pull the join amounts
+    /// where they fall short. Without the check, joins assume that the pool
+    /// is already approved.
+    pub fn with_allowance_check(mut self, settlement: Address) -> Self {
+        self.allowance_owner = Some(settlement);
+        self
+    }
+
+    /// Chain the encoder is connected to.
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    /// Helper contract used to generate template orders.
+    pub fn helper(&self) -> &BCowHelper {
+        &self.helper
+    }
+
+    /// Encodes a CowAMM swap for the three possible cases:
+    /// 1. Token A -> Token B (normal swap)
+    /// 2. Token A -> LP Token (join pool: swap + add liquidity)
+    /// 3. LP Token -> Token A (exit pool: remove liquidity + swap)
+    ///
+    /// # Arguments
+    /// * `kind` - Whether `amount` is the exact amount sold or bought
+    /// * `amount` - Amount of token_in to sell for sell orders, amount of
+    ///   token_out to buy for buy orders
+    /// * `token_in` - Address of the input token
+    /// * `token_out` - Address of the output token
+    /// * `pool_state` - Current state of the CowAMM pool for off-chain calculations
+    ///
+    /// Either token may be native ETH (`BUY_ETH_ADDRESS`) for pools that
+    /// trade WETH. ETH coming in is wrapped before the trade and WETH going
+    /// out is unwrapped after it. Only the ETH the trade, swaps and join use
+    /// is wrapped, and only the WETH the trade, exit and swaps return at
+    /// least is unwrapped.
+    ///
+    /// # Returns
+    /// A `TemplateOrder` with the main order and appropriate pre/post interactions
+    pub async fn encode(
+        &self,
+        kind: OrderKind,
+        amount: BigUint,
+        token_in: Bytes,
+        token_out: Bytes,
+        pool_state: Arc<dyn PoolState>,
+    ) -> Result<TemplateOrder, EncodeError> {
+        let pool_state = pool_state.as_ref();
+        // BCoW pools trade at least two tokens (`MIN_BOUND_TOKENS`), and the
+        // helper rejects pools with more.
+        match pool_state.tokens().len() {
+            0 | 1 => return Err(EncodeError::UnsupportedPoolState),
+            2 => (),
+            n => return Err(EncodeError::TooManyTokens(n)),
         }
+        // Convert BigUint to U256
+        let amount = biguint_to_u256(&amount);
         
-        // Case 2: Token A -> LP Token (Join Pool)
-        // User sells Token A, gets LP tokens
-        // Flow: Swap some Token A for Token B -> Join pool with both tokens
-        (false, true) => {
-            encode_join_pool_swap(
-                amount_in_u256,
-                token_in_addr,
-                pool_address,
-                pool_state,
-                &amm,
-            ).await
-        }
+        // Convert Bytes to Address
+        let token_in = bytes_to_address(&token_in)?;
+        let token_out = bytes_to_address(&token_out)?;
+
+        // Native ETH is traded as WETH by the pool
+        let wrap = token_in == BUY_ETH_ADDRESS;
+        let unwrap = token_out == BUY_ETH_ADDRESS;
+        let weth = match (wrap || unwrap, &self.weth) {
+            (false, _) => None,
+            (true, Some(weth)) => Some(weth),
+            (true, None) => return Err(EncodeError::MissingWeth(self.chain_id)),
+        };
+        let native_to_weth = |token| match weth {
+            Some(weth) if token == BUY_ETH_ADDRESS => weth.address(),
+            _ => token,
+        };
+        let token_in = native_to_weth(token_in);
+        let token_out = native_to_weth(token_out);
         
-        // Case 3: LP Token -> Token A (Exit Pool)
-        // User sells LP tokens, gets Token A
-        // Flow: Exit pool (burn LP, receive both tokens) -> Swap Token B for Token A
-        (true, false) => {
-            encode_exit_pool_swap(
-                amount_in_u256,
-                token_out_addr,
-                pool_address,
-                pool_state,
-                &amm,
-            ).await
-        }
+        // The helper is queried for the pool itself, not for its own address
+        let amm = Amm::new(pool_state.address(), &self.helper).await?;
+        validate_pool_tokens(&amm, pool_state)?;
+        
+        // Determine which case we're handling
+        let is_lp_in = token_in == pool_state.lp_token();
+        let is_lp_out = token_out == pool_state.lp_token();
         
-        // Invalid case: LP Token -> LP Token
-        (true, true) => {
-            anyhow::bail!("Cannot swap LP token for LP token")
+        let Encoded {
+            mut template,
+            paid,
+            received,
+        } = match (is_lp_in, is_lp_out) {
+            // Case 1: Normal Token A -> Token B swap
+            (false, false) => {
+                ensure_traded_token(&amm, token_in)?;
+                ensure_traded_token(&amm, token_out)?;
+                self.encode_normal_swap(
+                    kind,
+                    amount,
+                    token_in,
+                    token_out,
+                    &amm,
+                ).await
+            }
+            
+            // Case 2: Token A -> LP Token (Join Pool)
+            // User sells Token A, gets LP tokens
+            // Flow: Swap some Token A for the other tokens -> Join pool with all tokens
+            (false, true) => {
+                ensure_traded_token(&amm, token_in)?;
+                match kind {
+                    OrderKind::Sell => self.encode_join_pool_swap(
+                        amount,
+                        token_in,
+                        pool_state,
+                        &amm,
+                    ).await,
+                    OrderKind::Buy => self.encode_join_pool_buy(
+                        amount,
+                        token_in,
+                        pool_state,
+                        &amm,
+                    ).await,
+                }
+            }
+            
+            // Case 3: LP Token -> Token A (Exit Pool)
+            // User sells LP tokens, gets Token A
+            // Flow: Exit pool (burn LP, receive all tokens) -> Swap the others for Token A
+            (true, false) => {
+                ensure_traded_token(&amm, token_out)?;
+                match kind {
+                    OrderKind::Sell => self.encode_exit_pool_swap(
+                        amount,
+                        token_out,
+                        pool_state,
+                        &amm,
+                    ).await,
+                    OrderKind::Buy => self.encode_exit_pool_buy(
+                        amount,
+                        token_out,
+                        pool_state,
+                        &amm,
+                    ).await,
+                }
+            }
+            
+            // Invalid case: LP Token -> LP Token
+            (true, true) => Err(EncodeError::LpToLp),
+        }?;
+
+        if let Some(validation) = &self.signature_validation {
+            amm.validate_template(
+                &template,
+                validation.validator.as_ref(),
+                &validation.domain_separator,
+            )
+            .await
+            .map_err(EncodeError::InvalidSignature)?;
+        }
+
+        // Wrapping happens after the validation, which would otherwise
+        // depend on the ETH balance of the settlement contract.
+        if let Some(weth) = weth {
+            if wrap {
+                let deposit = WethDepositInteraction {
+                    weth: weth.clone(),
+                    amount: paid,
+                };
+                template.pre_interactions.insert(0, interaction_data(&deposit));
+            }
+            if unwrap {
+                let withdraw = WethWithdrawInteraction {
+                    weth: weth.clone(),
+                    amount: received,
+                };
+                template.post_interactions.push(interaction_data(&withdraw));
+            }
+        }
+
+        template.update_gas_estimate();
+        Ok(template)
+    }
+
+    /// Case 1: Normal token-to-token swap
+    /// No pre or post interactions needed
+    ///
+    /// The template is the pool's own order, so it buys the token the
+    /// settlement pays in and sells the token the settlement receives.
+    async fn encode_normal_swap(
+        &self,
+        kind: OrderKind,
+        amount: U256,
+        token_in: Address,
+        token_out: Address,
+        amm: &Amm,
+    ) -> Result<Encoded, EncodeError> {
+        // Generate template order for the swap
+        let template = match kind {
+            OrderKind::Sell => amm.template_order_from_buy_amount(token_in, amount).await,
+            OrderKind::Buy => amm.template_order_from_sell_amount(token_out, amount).await,
+        }
+        .map_err(EncodeError::from_helper)?;
+        ensure_order_trades(&template, token_in, token_out)?;
+
+        Ok(Encoded::swap(template))
+    }
+
+    /// Case 2: Token A -> LP Token (Join Pool)
+    /// Flow:
+    /// 1. Size the part of Token A to swap so that the rest and the bought
+    ///    tokens are proportional to the pool balances after the swaps
+    /// 2. Main order: Swap part of it for Token B
+    /// 3. Post-interactions: Swap the rest of it for every other pool token
+    ///    and join pool with all tokens
+    async fn encode_join_pool_swap(
+        &self,
+        amount_in: U256,
+        token_in: Address,
+        pool_state: &dyn PoolState,
+        amm: &Amm,
+    ) -> Result<Encoded, EncodeError> {
+        // Determine which token we're swapping and which we need to acquire
+        let tokens = pool_state.tokens();
+        let balances = pool_state.balances();
+        let weights = pool_state.weights();
+        let (i, o, others) = join_exit_indices(&tokens, token_in)?;
+
+        let swap_amount = sizing::swap_amount_for_join(
+            amount_in,
+            balances[i],
+            weights[i],
+            total_weight(&weights),
+        )
+        .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+
+        // Every other token loses the same share of its balance, and the
+        // pool swaps after the order end with `swap_amount` of Token A added
+        // to the pool. Size them backwards from there, so the order buys
+        // what is left.
+        let share = bnum::bdiv(amount_in - swap_amount, balances[i].saturating_add(amount_in))
+            .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+        let mut balance_in = balances[i].saturating_add(swap_amount);
+        let mut swap_amounts = vec![U256::zero(); tokens.len()];
+        for &k in others.iter().rev() {
+            let bought = bnum::bmul(balances[k], share)
+                .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+            let balance_before = sizing::balance_before_swap(
+                balance_in,
+                weights[i],
+                balances[k],
+                balances[k] - bought,
+                weights[k],
+            )
+            .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+            swap_amounts[k] = balance_in.saturating_sub(balance_before);
+            balance_in = balance_before;
+        }
+        let order_amount = balance_in.checked_sub(balances[i]).ok_or_else(|| {
+            EncodeError::PoolMath("pool swaps use up the swap amount".to_string())
+        })?;
+
+        // Main order: the pool buys that part of Token A and sells Token B
+        let mut template = amm
+            .template_order_from_buy_amount(token_in, order_amount)
+            .await
+            .map_err(EncodeError::from_helper)?;
+        ensure_order_trades(&template, token_in, tokens[o])?;
+
+        // The swaps and the join run after the trade, so they go against the
+        // balances the trade leaves behind.
+        let order = &template.order;
+        let mut available = vec![U256::zero(); tokens.len()];
+        let mut balances_after_swap = balances;
+        balances_after_swap[i] = balances_after_swap[i].saturating_add(order.buy_amount);
+        balances_after_swap[o] = balances_after_swap[o]
+            .checked_sub(order.sell_amount)
+            .ok_or_else(|| {
+                EncodeError::PoolMath("order sells more than the pool balance".to_string())
+            })?;
+        available[o] = order.sell_amount;
+        let mut swaps = Vec::new();
+        for &k in &others {
+            let bought = sizing::out_given_in(
+                balances_after_swap[i],
+                weights[i],
+                balances_after_swap[k],
+                weights[k],
+                swap_amounts[k],
+            )
+            .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+            balances_after_swap[i] = balances_after_swap[i].saturating_add(swap_amounts[k]);
+            balances_after_swap[k] -= bought;
+            available[k] = bought;
+            swaps.push(interaction_data(&SwapExactAmountInInteraction {
+                b_cow_pool: BCowPool::at(&self.web3, pool_state.address()),
+                token_in,
+                token_amount_in: swap_amounts[k],
+                token_out: tokens[k],
+                min_amount_out: self.min_amount_out(bought),
+                max_price: U256::MAX,
+            }));
+        }
+        let swapped = swap_amounts
+            .iter()
+            .fold(order.buy_amount, |total, amount| total.saturating_add(*amount));
+        available[i] = amount_in.checked_sub(swapped).ok_or_else(|| {
+            EncodeError::PoolMath(format!(
+                "order and pool swaps buy {swapped} but only {amount_in} is sold"
+            ))
+        })?;
+
+        let lp_supply = pool_state.lp_supply();
+        let lp_amount_out = sizing::lp_out_for_join(&available, &balances_after_swap, lp_supply)
+            .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+        let join_amounts =
+            sizing::proportional_amounts(lp_amount_out, lp_supply, &balances_after_swap)
+                .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+        if join_amounts.iter().zip(&available).any(|(joined, available)| joined > available) {
+            return Err(EncodeError::PoolMath(format!(
+                "join of {join_amounts:?} exceeds what is left of {amount_in} after the swaps"
+            )));
+        }
+
+        let paid = swapped.saturating_add(join_amounts[i]);
+
+        // Create the join pool interaction as a post-interaction
+        let join_interaction = JoinPoolInteraction {
+            b_cow_pool: BCowPool::at(&self.web3, pool_state.address()),
+            pool_amount_out: lp_amount_out, // Amount of LP tokens to mint
+            max_amounts_in: self.max_amounts_in(in_pool_order(amm, &tokens, join_amounts)?),
+        };
+
+        // The pool pulls Token A for the swaps and every token for the join
+        let mut approvals = join_interaction.max_amounts_in.clone();
+        let index_in = pool_index(amm, token_in)?;
+        approvals[index_in] = approvals[index_in].saturating_add(swapped - order.buy_amount);
+        self.append_approvals(&mut template, amm, &approvals).await?;
+        template.post_interactions.extend(swaps);
+        template.post_interactions.push(interaction_data(&join_interaction));
+        template.slippage_bps = Some(self.slippage_bps);
+
+        Ok(Encoded {
+            template,
+            paid,
+            received: lp_amount_out,
+        })
+    }
+
+    /// Case 2 for buy orders: Token A -> exactly `lp_amount_out` LP tokens
+    /// Flow:
+    /// 1. Size the amount of every other token to buy so that it covers the
+    ///    join after the purchase
+    /// 2. Main order: The pool sells that Token B for Token A
+    /// 3. Post-interactions: Buy the other tokens from the pool with Token A
+    ///    and join pool with all tokens
+    async fn encode_join_pool_buy(
+        &self,
+        lp_amount_out: U256,
+        token_in: Address,
+        pool_state: &dyn PoolState,
+        amm: &Amm,
+    ) -> Result<Encoded, EncodeError> {
+        let tokens = pool_state.tokens();
+        let balances = pool_state.balances();
+        let weights = pool_state.weights();
+        let (i, o, others) = join_exit_indices(&tokens, token_in)?;
+        let lp_supply = pool_state.lp_supply();
+
+        let mut bought = vec![U256::zero(); tokens.len()];
+        for k in (0..tokens.len()).filter(|k| *k != i) {
+            bought[k] = sizing::swap_out_for_join(lp_amount_out, balances[k], lp_supply)
+                .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+        }
+        // The helper only approximates the sell amount, so ask for a little
+        // more than the join needs.
+        let mut template = amm
+            .template_order_from_sell_amount(tokens[o], bought[o] + rounding_tolerance(bought[o]))
+            .await
+            .map_err(EncodeError::from_helper)?;
+        ensure_order_trades(&template, token_in, tokens[o])?;
+
+        // The swaps and the join run after the trade, so they go against the
+        // balances the trade leaves behind.
+        let order = &template.order;
+        let mut balances_after_swap = balances;
+        balances_after_swap[i] = balances_after_swap[i].saturating_add(order.buy_amount);
+        balances_after_swap[o] = balances_after_swap[o]
+            .checked_sub(order.sell_amount)
+            .ok_or_else(|| {
+                EncodeError::PoolMath("order sells more than the pool balance".to_string())
+            })?;
+        bought[o] = order.sell_amount;
+        let mut swaps = Vec::new();
+        let mut swapped = U256::zero();
+        let mut paid = order.buy_amount;
+        for &k in &others {
+            let swap_amount = sizing::in_given_out(
+                balances_after_swap[i],
+                weights[i],
+                balances_after_swap[k],
+                weights[k],
+                bought[k],
+            )
+            .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+            let max_amount_in = self.max_amount_in(swap_amount + rounding_tolerance(swap_amount));
+            balances_after_swap[i] = balances_after_swap[i].saturating_add(swap_amount);
+            balances_after_swap[k] -= bought[k];
+            swapped = swapped.saturating_add(max_amount_in);
+            paid = paid.saturating_add(swap_amount);
+            swaps.push(interaction_data(&SwapExactAmountOutInteraction {
+                b_cow_pool: BCowPool::at(&self.web3, pool_state.address()),
+                token_in,
+                max_amount_in,
+                token_out: tokens[k],
+                token_amount_out: bought[k],
+                max_price: U256::MAX,
+            }));
+        }
+
+        let join_amounts =
+            sizing::proportional_amounts(lp_amount_out, lp_supply, &balances_after_swap)
+                .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+        for k in (0..tokens.len()).filter(|k| *k != i) {
+            if join_amounts[k] > bought[k] {
+                return Err(EncodeError::PoolMath(format!(
+                    "join needs {} of {:?} but only {} is bought",
+                    join_amounts[k], tokens[k], bought[k]
+                )));
+            }
+        }
+
+        let paid = paid.saturating_add(join_amounts[i]);
+        let join_interaction = JoinPoolInteraction {
+            b_cow_pool: BCowPool::at(&self.web3, pool_state.address()),
+            pool_amount_out: lp_amount_out,
+            max_amounts_in: self.max_amounts_in(in_pool_order(amm, &tokens, join_amounts)?),
+        };
+        let mut approvals = join_interaction.max_amounts_in.clone();
+        let index_in = pool_index(amm, token_in)?;
+        approvals[index_in] = approvals[index_in].saturating_add(swapped);
+        self.append_approvals(&mut template, amm, &approvals).await?;
+        template.post_interactions.extend(swaps);
+        template.post_interactions.push(interaction_data(&join_interaction));
+        template.slippage_bps = Some(self.slippage_bps);
+
+        Ok(Encoded {
+            template,
+            paid,
+            received: lp_amount_out,
+        })
+    }
+
+    /// Case 3: LP Token -> Token A (Exit Pool)
+    /// Flow:
+    /// 1. Pre-interaction: Exit pool (burn LP tokens, receive every pool token)
+    /// 2. Main order: The pool buys the received Token B for Token A
+    /// 3. Post-interactions: Swap every other received token for Token A
+    async fn encode_exit_pool_swap(
+        &self,
+        lp_amount_in: U256,
+        token_out: Address,
+        pool_state: &dyn PoolState,
+        amm: &Amm,
+    ) -> Result<Encoded, EncodeError> {
+        // Determine which token to keep and which to swap
+        let tokens = pool_state.tokens();
+        let (i, o, others) = join_exit_indices(&tokens, token_out)?;
+
+        // Calculate the proportional amounts of every token we'll receive from exiting
+        let exit_amounts = pool_state
+            .exit_amounts_out(lp_amount_in)
+            .map_err(|err| EncodeError::PoolMath(format!("{err:#}")))?;
+        let min_amounts_out = self.min_amounts_out(exit_amounts.clone());
+        let exited = exit_balances(pool_state, &exit_amounts)?;
+
+        // Generate the main swap order
+        // The pool buys no more of the unwanted token than the exit is
+        // guaranteed to return
+        let helper_template = amm
+            .template_order_from_buy_amount(tokens[o], min_amounts_out[o])
+            .await
+            .map_err(EncodeError::from_helper)?;
+        ensure_order_trades(&helper_template, tokens[o], token_out)?;
+        let mut template = self.order_after_exit(
+            &helper_template,
+            amm,
+            pool_state,
+            &exited,
+            tokens[o],
+            min_amounts_out[o],
+        )?;
+
+        // The remaining tokens are swapped after the trade
+        let order = &template.order;
+        let mut balances = exited;
+        balances[o] = balances[o].saturating_add(order.buy_amount);
+        balances[i] = balances[i].checked_sub(order.sell_amount).ok_or_else(|| {
+            EncodeError::PoolMath("order sells more than the pool balance".to_string())
+        })?;
+        let (swaps, swapped) =
+            self.exit_swaps(pool_state, &others, i, &min_amounts_out, &mut balances)?;
+        let received = order
+            .sell_amount
+            .saturating_add(min_amounts_out[i])
+            .saturating_add(swapped);
+
+        // Create the exit pool interaction as a pre-interaction
+        let exit_interaction = ExitPoolInteraction {
+            b_cow_pool: BCowPool::at(&self.web3, pool_state.address()),
+            pool_amount_in: lp_amount_in, // Amount of LP tokens to burn
+            min_amounts_out: in_pool_order(amm, &tokens, min_amounts_out)?,
+        };
+
+        // Add exit pool as pre-interaction
+        template.pre_interactions.push(interaction_data(&exit_interaction));
+        self.append_swap_approvals(&mut template, amm, &tokens, &others, &exit_interaction)
+            .await?;
+        template.post_interactions.extend(swaps);
+        template.slippage_bps = Some(self.slippage_bps);
+
+        Ok(Encoded {
+            template,
+            paid: lp_amount_in,
+            received,
+        })
+    }
+
+    /// Case 3 for buy orders: LP Token -> exactly `amount_out` of Token A
+    /// Flow:
+    /// 1. Size the LP amount whose exit plus the swaps of the other received
+    ///    tokens yields `amount_out` of Token A
+    /// 2. Pre-interaction: Exit pool with that LP amount
+    /// 3. Main order: The pool buys the received Token B for Token A
+    /// 4. Post-interactions: Swap every other received token for Token A
+    async fn encode_exit_pool_buy(
+        &self,
+        amount_out: U256,
+        token_out: Address,
+        pool_state: &dyn PoolState,
+        amm: &Amm,
+    ) -> Result<Encoded, EncodeError> {
+        let tokens = pool_state.tokens();
+        let balances = pool_state.balances();
+        let weights = pool_state.weights();
+        let (i, o, others) = join_exit_indices(&tokens, token_out)?;
+
+        let lp_amount_in = sizing::lp_in_for_exact_token_out(
+            amount_out,
+            balances[i],
+            weights[i],
+            total_weight(&weights),
+            pool_state.lp_supply(),
+        )
+        .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+
+        let exit_amounts = pool_state
+            .exit_amounts_out(lp_amount_in)
+            .map_err(|err| EncodeError::PoolMath(format!("{err:#}")))?;
+        let min_amounts_out = self.min_amounts_out(exit_amounts.clone());
+
+        let missing = amount_out
+            .checked_sub(exit_amounts[i])
+            .filter(|amount| !amount.is_zero())
+            .ok_or_else(|| EncodeError::PoolMath("exit already covers buy amount".to_string()))?;
+
+        // The pool swaps after the order end with `missing` of Token A taken
+        // out of the pool after the exit. Size them backwards from there, so
+        // the order sells what is left.
+        let exited = exit_balances(pool_state, &exit_amounts)?;
+        let mut balance_out = exited[i].checked_sub(missing).ok_or_else(|| {
+            EncodeError::PoolMath("buy amount exceeds the pool balance".to_string())
+        })?;
+        for &k in others.iter().rev() {
+            balance_out = sizing::balance_before_swap(
+                balance_out,
+                weights[i],
+                exited[k],
+                balances[k],
+                weights[k],
+            )
+            .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+        }
+        let order_amount = exited[i].checked_sub(balance_out).ok_or_else(|| {
+            EncodeError::PoolMath("pool swaps cover the buy amount".to_string())
+        })?;
+
+        // The pool buys the other token received from the exit for the
+        // remainder of the requested token.
+        let helper_template = amm
+            .template_order_from_buy_amount(tokens[o], exit_amounts[o])
+            .await
+            .map_err(EncodeError::from_helper)?;
+        ensure_order_trades(&helper_template, tokens[o], token_out)?;
+        let mut template = self.order_after_exit(
+            &helper_template,
+            amm,
+            pool_state,
+            &exited,
+            tokens[o],
+            exit_amounts[o],
+        )?;
+
+        // The sizing approximates the pool math, so the order only sells the
+        // remainder up to rounding.
+        let order = &template.order;
+        if order.sell_amount.saturating_add(rounding_tolerance(order_amount)) < order_amount {
+            return Err(EncodeError::PoolMath(format!(
+                "order sells {} instead of {order_amount}",
+                order.sell_amount
+            )));
+        }
+
+        let mut balances = exited;
+        balances[o] = balances[o].saturating_add(order.buy_amount);
+        balances[i] = balances[i].checked_sub(order.sell_amount).ok_or_else(|| {
+            EncodeError::PoolMath("order sells more than the pool balance".to_string())
+        })?;
+        let (swaps, swapped) =
+            self.exit_swaps(pool_state, &others, i, &min_amounts_out, &mut balances)?;
+        let received = order
+            .sell_amount
+            .saturating_add(min_amounts_out[i])
+            .saturating_add(swapped);
+
+        let exit_interaction = ExitPoolInteraction {
+            b_cow_pool: BCowPool::at(&self.web3, pool_state.address()),
+            pool_amount_in: lp_amount_in,
+            min_amounts_out: in_pool_order(amm, &tokens, min_amounts_out)?,
+        };
+        template.pre_interactions.push(interaction_data(&exit_interaction));
+        self.append_swap_approvals(&mut template, amm, &tokens, &others, &exit_interaction)
+            .await?;
+        template.post_interactions.extend(swaps);
+        template.slippage_bps = Some(self.slippage_bps);
+
+        Ok(Encoded {
+            template,
+            paid: lp_amount_in,
+            received,
+        })
+    }
+
+    /// Prices the order of an exit, in which the pool buys `buy_amount` of
+    /// `buy_token`, on `exited`, the pool balances after the exit.
+    ///
+    /// The exit runs as a pre-interaction, so the pool verifies the order on
+    /// these balances while the helper prices it on the current ones. The
+    /// helper's `template` only provides the block the order is valid from.
+    fn order_after_exit(
+        &self,
+        template: &TemplateOrder,
+        amm: &Amm,
+        pool_state: &dyn PoolState,
+        exited: &[U256],
+        buy_token: Address,
+        buy_amount: U256,
+    ) -> Result<TemplateOrder, EncodeError> {
+        let tokens = pool_state.tokens();
+        let snapshot = PoolSnapshot::new(
+            pool_state.address(),
+            amm.traded_tokens(),
+            &in_pool_order(amm, &tokens, exited.to_vec())?,
+            &in_pool_order(amm, &tokens, pool_state.weights())?,
+            self.domain_separator()?,
+        )?;
+        // The helper makes orders valid for a fixed duration from the block.
+        let timestamp = template.order.valid_to.saturating_sub(MAX_ORDER_DURATION);
+        Ok(snapshot.order_from_buy_amount(buy_token, buy_amount, timestamp.into())?)
+    }
+
+    /// Domain separator of the settlement contract the pools sign orders
+    /// for.
+    fn domain_separator(&self) -> Result<DomainSeparator, EncodeError> {
+        if let Some(validation) = &self.signature_validation {
+            return Ok(DomainSeparator(validation.domain_separator.0));
         }
+        let settlement = contracts::deployment(GPv2Settlement::raw_contract(), self.chain_id)
+            .map_err(|_| EncodeError::MissingSettlement(self.chain_id))?
+            .address;
+        Ok(DomainSeparator::new(self.chain_id, settlement))
+    }
+
+    /// Swaps the exit amounts of the `others` tokens for the token at index
+    /// `i` after the trade, updating `balances` accordingly. Also returns the
+    /// least amount of that token the swaps return in total.
+    fn exit_swaps(
+        &self,
+        pool_state: &dyn PoolState,
+        others: &[usize],
+        i: usize,
+        amounts_in: &[U256],
+        balances: &mut [U256],
+    ) -> Result<(Vec<InteractionData>, U256), EncodeError> {
+        let tokens = pool_state.tokens();
+        let weights = pool_state.weights();
+        let mut swaps = Vec::new();
+        let mut swapped = U256::zero();
+        for &k in others {
+            let amount_out = sizing::out_given_in(
+                balances[k],
+                weights[k],
+                balances[i],
+                weights[i],
+                amounts_in[k],
+            )
+            .map_err(|err| EncodeError::PoolMath(err.to_string()))?;
+            balances[k] = balances[k].saturating_add(amounts_in[k]);
+            balances[i] -= amount_out;
+            let min_amount_out = self.min_amount_out(amount_out);
+            swapped = swapped.saturating_add(min_amount_out);
+            swaps.push(interaction_data(&SwapExactAmountInInteraction {
+                b_cow_pool: BCowPool::at(&self.web3, pool_state.address()),
+                token_in: tokens[k],
+                token_amount_in: amounts_in[k],
+                token_out: tokens[i],
+                min_amount_out,
+                max_price: U256::MAX,
+            }));
+        }
+        Ok((swaps, swapped))
+    }
+
+    /// Approves the pool to pull the exit amounts of the `others` tokens,
+    /// which are swapped after the trade.
+    async fn append_swap_approvals(
+        &self,
+        template: &mut TemplateOrder,
+        amm: &Amm,
+        tokens: &[Address],
+        others: &[usize],
+        exit: &ExitPoolInteraction,
+    ) -> Result<(), EncodeError> {
+        let mut approvals = vec![U256::zero(); exit.min_amounts_out.len()];
+        for &k in others {
+            let index = pool_index(amm, tokens[k])?;
+            approvals[index] = exit.min_amounts_out[index];
+        }
+        self.append_approvals(template, amm, &approvals).await
+    }
+
+    /// Appends the approvals the pool needs to pull `amounts`, which are in
+    /// the order of the pool's tokens, if allowances are checked.
+    async fn append_approvals(
+        &self,
+        template: &mut TemplateOrder,
+        amm: &Amm,
+        amounts: &[U256],
+    ) -> Result<(), EncodeError> {
+        let Some(owner) = self.allowance_owner else {
+            return Ok(());
+        };
+        let pool = *amm.address();
+        for (token, amount) in amm.traded_tokens().iter().zip(amounts) {
+            if amount.is_zero() {
+                continue;
+            }
+            let token = ERC20::at(&self.web3, *token);
+            let allowance = token.allowance(owner, pool).call().await?;
+            if allowance >= *amount {
+                continue;
+            }
+            // Some tokens, like USDT, only allow changing an allowance
+            // from or to zero.
+            if !allowance.is_zero() {
+                let reset = Erc20ApproveInteraction {
+                    token: token.clone(),
+                    spender: pool,
+                    amount: U256::zero(),
+                };
+                template.post_interactions.push(interaction_data(&reset));
+            }
+            let approve = Erc20ApproveInteraction {
+                token,
+                spender: pool,
+                amount: *amount,
+            };
+            template.post_interactions.push(interaction_data(&approve));
+        }
+        Ok(())
+    }
+
+    /// Raises join amounts by the slippage tolerance.
+    fn max_amounts_in(&self, amounts: Vec<U256>) -> Vec<U256> {
+        amounts.into_iter().map(|amount| self.max_amount_in(amount)).collect()
+    }
+
+    /// Lowers exit amounts by the slippage tolerance.
+    fn min_amounts_out(&self, amounts: Vec<U256>) -> Vec<U256> {
+        amounts.into_iter().map(|amount| self.min_amount_out(amount)).collect()
+    }
+
+    /// Raises a join amount by the slippage tolerance, rounding up.
+    fn max_amount_in(&self, amount: U256) -> U256 {
+        let factor = MAX_SLIPPAGE_BPS + self.slippage_bps;
+        let scaled = (amount.full_mul(factor.into()) + (MAX_SLIPPAGE_BPS - 1)) / MAX_SLIPPAGE_BPS;
+        U256::try_from(scaled).unwrap_or(U256::MAX)
+    }
+
+    /// Lowers an exit amount by the slippage tolerance, rounding down.
+    fn min_amount_out(&self, amount: U256) -> U256 {
+        let factor = MAX_SLIPPAGE_BPS - self.slippage_bps;
+        let scaled = amount.full_mul(factor.into()) / MAX_SLIPPAGE_BPS;
+        U256::try_from(scaled).expect("scaled down amount fits")
     }
 }
 
-/// Case 1: Normal token-to-token swap
-/// No pre or post interactions needed
-async fn encode_normal_swap(
-    amount_in: U256,
-    token_in: Address,
-    token_out: Address,
-    amm: &Amm,
-) -> Result<TemplateOrder> {
-    // Generate template order for the swap
-    let template = amm
-        .template_order_from_sell_amount(token_in, amount_in)
-        .await
-        .context("Failed to generate template order for normal swap")?;
-    
-    Ok(template)
+// Helper functions
+/// Checks that the tokens the helper reports for the pool are the ones the
+/// off-chain state was built for, so orders are not generated against a pool
+/// the state does not describe.
+fn validate_pool_tokens(amm: &Amm, pool_state: &dyn PoolState) -> Result<(), EncodeError> {
+    let mut expected = pool_state.tokens();
+    let mut actual = amm.traded_tokens().to_vec();
+    expected.sort();
+    actual.sort();
+
+    if expected != actual {
+        return Err(EncodeError::PoolTokenMismatch {
+            pool: *amm.address(),
+            actual,
+            expected,
+        });
+    }
+    Ok(())
 }
 
-/// Case 2: Token A -> LP Token (Join Pool)
-/// Flow:
-/// 1. Calculate proportional amounts needed for joining
-/// 2. Main order: Swap excess Token A for Token B
-/// 3. Post-interaction: Join pool with both tokens
-async fn encode_join_pool_swap(
-    amount_in: U256,
-    token_in: Address,
-    pool_address: Address,
-    pool_state: &CowAMMState,
-    amm: &Amm,
-) -> Result<TemplateOrder> {
-    // Calculate the proportional amounts of both tokens needed to join the pool
-    // This represents what we'll get when we "buy" the LP token amount
+/// Checks that `token` is one of the tokens traded by the pool.
+fn ensure_traded_token(amm: &Amm, token: Address) -> Result<(), EncodeError> {
+    if !amm.traded_tokens().contains(&token) {
+        return Err(EncodeError::UnknownToken(token));
+    }
+    Ok(())
+}
 
-    let (proportional_token_a, proportional_token_b) = pool_state
-        .calc_tokens_out_given_exact_lp_token_in(ethcontract_to_alloy(amount_in))
-        .context("Failed to calculate proportional token amounts")?;
-    
-    // Determine which token we're swapping and which we need to acquire
-    let token_a_addr = bytes_to_address(&pool_state.token_a.0)?;
-    let token_b_addr = bytes_to_address(&pool_state.token_b.0)?;
-    
-    let (swap_sell_token, swap_buy_token, swap_amount) = if token_in == token_a_addr {
-        // We have Token A, need to swap for Token B
-        (token_in, token_b_addr, proportional_token_b)
-    } else {
-        // We have Token B, need to swap for Token A
-        (token_in, token_a_addr, proportional_token_a)
-    };
-    
-    // Generate the main swap order
-    // This swaps the excess token to get the proportional amount of the other token
-    let mut template = amm
-        .template_order_from_sell_amount(swap_sell_token, alloy_to_ethcontract(swap_amount))
-        .await
-        .context("Failed to generate swap order for join pool")?;
-    
-    // Create the join pool interaction as a post-interaction
-    let join_interaction = JoinPoolInteraction {
-        b_cow_pool: contract!(BCowPool, pool_address),
-        pool_amount_out: amount_in, // Amount of LP tokens to mint
-        max_amounts_in: vec![
-            alloy_to_ethcontract(proportional_token_a), 
-            alloy_to_ethcontract(proportional_token_b)
-        ],
-    };
-    
-    let encoded_join = join_interaction.encode_join();
-    
-    // Add join pool as post-interaction
-    template.post_interactions.push(InteractionData {
-        target: encoded_join.0,
-        value: encoded_join.1,
-        call_data: encoded_join.2.0.into(),
-    });
-    
-    Ok(template)
+/// Checks that the pool's order buys `buy_token` from and sells `sell_token`
+/// to the settlement, which a buggy helper might not.
+fn ensure_order_trades(
+    template: &TemplateOrder,
+    buy_token: Address,
+    sell_token: Address,
+) -> Result<(), EncodeError> {
+    let order = &template.order;
+    if order.buy_token != buy_token || order.sell_token != sell_token {
+        return Err(EncodeError::InvalidHelperResponse(anyhow::anyhow!(
+            "order buys {:?} for {:?} instead of {buy_token:?} for {sell_token:?}",
+            order.buy_token,
+            order.sell_token,
+        )));
+    }
+    Ok(())
+}
+
+/// Largest difference between an amount computed off-chain and the one the
+/// helper computes for it. Both approximate powers with `bpow`, which is
+/// accurate to `BPOW_PRECISION` (1e-10), so allow ten times that plus a few
+/// wei of rounding.
+fn rounding_tolerance(amount: U256) -> U256 {
+    amount / 1_000_000_000 + 2
+}
+
+/// Indices of `token`, of the token the order of a single-sided join or exit
+/// trades it with, and of the remaining tokens, which are swapped through the
+/// pool after the trade.
+fn join_exit_indices(
+    tokens: &[Address],
+    token: Address,
+) -> Result<(usize, usize, Vec<usize>), EncodeError> {
+    let i = tokens
+        .iter()
+        .position(|t| *t == token)
+        .ok_or(EncodeError::UnknownToken(token))?;
+    let mut others = (0..tokens.len()).filter(|k| *k != i);
+    let o = others.next().ok_or(EncodeError::UnsupportedPoolState)?;
+    Ok((i, o, others.collect()))
 }
 
-/// Case 3: LP Token -> Token A (Exit Pool)
-/// Flow:
-/// 1. Pre-interaction: Exit pool (burn LP tokens, receive both Token A and Token B)
-/// 2. Main order: Swap Token B for Token A
-async fn encode_exit_pool_swap(
-    lp_amount_in: U256,
-    token_out: Address,
-    pool_address: Address,
-    pool_state: &CowAMMState,
+/// Pool balances after an exit returned `exit_amounts`.
+fn exit_balances(
+    pool_state: &dyn PoolState,
+    exit_amounts: &[U256],
+) -> Result<Vec<U256>, EncodeError> {
+    pool_state
+        .balances()
+        .iter()
+        .zip(exit_amounts)
+        .map(|(balance, amount)| {
+            balance.checked_sub(*amount).ok_or_else(|| {
+                EncodeError::PoolMath("exit returns more than the pool balance".to_string())
+            })
+        })
+        .collect()
+}
+
+/// Sum of the denormalized weights of all pool tokens.
+fn total_weight(weights: &[U256]) -> U256 {
+    weights
+        .iter()
+        .fold(U256::zero(), |total, weight| total.saturating_add(*weight))
+}
+
+/// Reorders `amounts` of the pool state's `tokens` into the order of the
+/// pool's `getFinalTokens`, which the helper reports and `joinPool` and
+/// `exitPool` expect.
+fn in_pool_order(
     amm: &Amm,
-) -> Result<TemplateOrder> {
-    // Calculate the proportional amounts of both tokens we'll receive from exiting
-    let (proportional_token_a, proportional_token_b) = pool_state
-        .calc_tokens_out_given_exact_lp_token_in(ethcontract_to_alloy(lp_amount_in))
-        .context("Failed to calculate tokens out for exit")?;
-    
-    // Determine which token to keep and which to swap
-    let token_a_addr = bytes_to_address(&pool_state.token_a.0)?;
-    let token_b_addr = bytes_to_address(&pool_state.token_b.0)?;
-    
-    let (swap_sell_token, swap_amount) = if token_out == token_a_addr {
-        // We want Token A, so swap Token B
-        (token_b_addr, proportional_token_b)
+    tokens: &[Address],
+    amounts: Vec<U256>,
+) -> Result<Vec<U256>, EncodeError> {
+    amm.traded_tokens()
+        .iter()
+        .map(|token| {
+            let index = tokens
+                .iter()
+                .position(|t| t == token)
+                .ok_or(EncodeError::UnknownToken(*token))?;
+            Ok(amounts[index])
+        })
+        .collect()
+}
+
+/// Index of `token` in the pool's `getFinalTokens`.
+fn pool_index(amm: &Amm, token: Address) -> Result<usize, EncodeError> {
+    amm.traded_tokens()
+        .iter()
+        .position(|t| *t == token)
+        .ok_or(EncodeError::UnknownToken(token))
+}
+
+/// Amount of `token` the template order trades.
+pub(crate) fn traded_amount(
+    template: &TemplateOrder,
+    token: Address,
+) -> Result<U256, EncodeError> {
+    let order = &template.order;
+    if order.buy_token == token {
+        Ok(order.buy_amount)
+    } else if order.sell_token == token {
+        Ok(order.sell_amount)
     } else {
-        // We want Token B, so swap Token A
-        (token_a_addr, proportional_token_a)
-    };
-    
-    // Generate the main swap order
-    // This swaps the unwanted token for more of the desired token
-    let mut template = amm
-        .template_order_from_sell_amount(swap_sell_token, alloy_to_ethcontract(swap_amount))
-        .await
-        .context("Failed to generate swap order for exit pool")?;
-    
-    // Create the exit pool interaction as a pre-interaction
-    let exit_interaction = ExitPoolInteraction {
-        b_cow_pool: contract!(BCowPool, pool_address),
-        pool_amount_in: lp_amount_in, // Amount of LP tokens to burn
-        min_amounts_out: vec![
-            alloy_to_ethcontract(proportional_token_a), 
-            alloy_to_ethcontract(proportional_token_b)
-        ],
-    };
-    
-    let encoded_exit = exit_interaction.encode_exit();
-    
-    // Add exit pool as pre-interaction
-    template.pre_interactions.push(InteractionData {
-        target: encoded_exit.0,
-        value: encoded_exit.1,
-        call_data: encoded_exit.2.0.into(),
-    });
-    
-    Ok(template)
+        Err(EncodeError::InvalidHelperResponse(anyhow::anyhow!(
+            "order does not trade {token:?}"
+        )))
+    }
+}
+
+/// Converts an interaction into the form used by template orders.
+pub(crate) fn interaction_data(interaction: &impl Interaction) -> InteractionData {
+    let (target, value, call_data) = interaction.encode();
+    InteractionData {
+        target,
+        value,
+        call_data: call_data.0,
+    }
 }
 
-// Helper functions
 /// Converts BigUint to U256
 fn biguint_to_u256(value: &BigUint) -> U256 {
     let bytes = value.to_bytes_be();
@@ -277,9 +1142,9 @@ fn biguint_to_u256(value: &BigUint) -> U256 {
 }
 
 /// Converts Bytes to Address
-fn bytes_to_address(bytes: &Bytes) -> Result<Address> {
+pub(crate) fn bytes_to_address(bytes: &Bytes) -> Result<Address, EncodeError> {
     if bytes.len() != 20 {
-        anyhow::bail!("Invalid address length: expected 20 bytes, got {}", bytes.len());
+        return Err(EncodeError::InvalidAddress(bytes.len()));
     }
     Ok(Address::from_slice(bytes.as_ref()))
 }
@@ -299,4 +1164,1275 @@ pub fn ethcontract_to_alloy(value: U256) -> AlloyU256 {
     
     // Create alloy U256 from big-endian bytes
     AlloyU256::from_be_bytes(bytes)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            decode_settlement::{decode_interaction, Call},
+            testing::{self, address_bytes, HelperResponse},
+        },
+        cow_amm::bmath,
+        ethcontract::H160,
+        ethcontract_mock::Mock,
+        hex_literal::hex,
+        model::order::OrderData,
+        shared::signature_validator::{SignatureCheck, SignatureValidationError},
+        tycho_simulation::evm::protocol::cowamm::state::CowAMMState,
+    };
+
+    const POOL: Address = H160([0x11; 20]);
+    const TOKEN_A: Address = H160([0xaa; 20]);
+    const TOKEN_B: Address = H160([0xbb; 20]);
+    const WETH: Address = H160([0xcc; 20]);
+
+    fn pool_state() -> CowAMMState {
+        testing::pool_state(POOL, TOKEN_A, TOKEN_B)
+    }
+
+    fn helper_response(
+        sell_token: Address,
+        buy_token: Address,
+        sell_amount: U256,
+        buy_amount: U256,
+    ) -> HelperResponse {
+        testing::helper_response(POOL, sell_token, buy_token, sell_amount, buy_amount)
+    }
+
+    #[tokio::test]
+    async fn encodes_normal_swap_through_injected_transport() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        // Selling 1000 token A means the pool buys exactly that amount.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .returns(helper_response(TOKEN_B, TOKEN_A, 990.into(), 1_000.into()));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(TOKEN_A),
+                address_bytes(TOKEN_B),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(encoder.chain_id(), 1);
+        assert_eq!(template.order.sell_token, TOKEN_B);
+        assert_eq!(template.order.buy_token, TOKEN_A);
+        assert_eq!(template.order.sell_amount, 990.into());
+        assert_eq!(template.order.buy_amount, 1_000.into());
+        assert_eq!(template.order.kind, OrderKind::Sell);
+        assert_eq!(template.signature.to_bytes(), vec![0xff; 32]);
+        assert_eq!(template.pre_interactions.len(), 1);
+        assert!(template.post_interactions.is_empty());
+        assert_eq!(template.slippage_bps, None);
+    }
+
+    #[tokio::test]
+    async fn reuses_encoder_across_calls() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .times(2)
+            .returns(helper_response(TOKEN_A, TOKEN_B, 495.into(), 500.into()));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let state: Arc<dyn PoolState> = Arc::new(pool_state());
+        for _ in 0..2 {
+            let template = encoder
+                .encode(
+                    OrderKind::Sell,
+                    BigUint::from(500_u32),
+                    address_bytes(TOKEN_B),
+                    address_bytes(TOKEN_A),
+                    state.clone(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(template.order.buy_amount, 500.into());
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_helper_for_pool_address() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .once()
+            .predicate_fn(|(pool,)| *pool == POOL)
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .predicate_fn(|(pool, buy_token, _)| *pool == POOL && *buy_token == TOKEN_A)
+            .returns(helper_response(TOKEN_B, TOKEN_A, 990.into(), 1_000.into()));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(TOKEN_A),
+                address_bytes(TOKEN_B),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(template.order.receiver, Some(POOL));
+        assert_ne!(helper.address(), POOL);
+    }
+
+    #[tokio::test]
+    async fn rejects_pool_with_mismatching_tokens() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, H160([0xcc; 20])]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .never();
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let result = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(TOKEN_A),
+                address_bytes(TOKEN_B),
+                Arc::new(pool_state()),
+            )
+            .await;
+
+        assert!(matches!(result, Err(EncodeError::PoolTokenMismatch { .. })));
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_not_traded_by_pool() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let unknown = H160([0xcc; 20]);
+        let result = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(TOKEN_A),
+                address_bytes(unknown),
+                Arc::new(pool_state()),
+            )
+            .await;
+        assert!(matches!(result, Err(EncodeError::UnknownToken(token)) if token == unknown));
+
+        let result = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(POOL),
+                address_bytes(POOL),
+                Arc::new(pool_state()),
+            )
+            .await;
+        assert!(matches!(result, Err(EncodeError::LpToLp)));
+    }
+
+    #[tokio::test]
+    async fn encodes_buy_order_for_normal_swap() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .never();
+        // Buying 990 token B means the pool sells that amount.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_sell_amount())
+            .once()
+            .predicate_fn(|(pool, sell_token, sell_amount)| {
+                *pool == POOL && *sell_token == TOKEN_B && *sell_amount == 990.into()
+            })
+            .returns(helper_response(TOKEN_B, TOKEN_A, 990.into(), 1_000.into()));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let template = encoder
+            .encode(
+                OrderKind::Buy,
+                BigUint::from(990_u32),
+                address_bytes(TOKEN_A),
+                address_bytes(TOKEN_B),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(template.order.sell_amount, 990.into());
+        assert_eq!(template.order.buy_amount, 1_000.into());
+        assert!(template.post_interactions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sizes_single_sided_join() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        // Joining the 1000/1000 pool with 210 token A sells about 100 of it
+        // to the pool for about 90.9 token B and joins with the rest.
+        let swap_amount = U256::from(99_999_999_991_934_267_000_u128);
+        let bought = U256::from(90_909_090_902_425_014_050_u128);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .predicate_fn(move |(_, buy_token, buy_amount)| {
+                *buy_token == TOKEN_A && *buy_amount == swap_amount
+            })
+            .returns(helper_response(TOKEN_B, TOKEN_A, bought, swap_amount));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(210_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(TOKEN_A),
+                address_bytes(POOL),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+
+        // Both are about 10% of the pool after the swap, which mints about
+        // 10% of the supply. The join never pulls more than is left.
+        let join_amount_a = U256::exp10(18) * 210 - swap_amount;
+        let balances_after_swap = [U256::exp10(21) + swap_amount, U256::exp10(21) - bought];
+        let lp_amount = sizing::lp_out_for_join(
+            &[join_amount_a, bought],
+            &balances_after_swap,
+            U256::exp10(20),
+        )
+        .unwrap();
+        assert!(lp_amount > U256::exp10(19) * 9_999 / 10_000 && lp_amount <= U256::exp10(19));
+        let join_amounts =
+            sizing::proportional_amounts(lp_amount, U256::exp10(20), &balances_after_swap)
+                .unwrap();
+        assert!(join_amounts[0] <= join_amount_a && join_amounts[1] <= bought);
+        assert_eq!(
+            template.post_interactions[0].call_data,
+            interaction_data(&JoinPoolInteraction {
+                b_cow_pool: BCowPool::at(&mock.web3(), POOL),
+                pool_amount_out: lp_amount,
+                max_amounts_in: join_amounts,
+            })
+            .call_data
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_join_order_buying_more_than_sold() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        // The pool buys more token A than the settlement has.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .returns(helper_response(
+                TOKEN_B,
+                TOKEN_A,
+                U256::exp10(20),
+                U256::exp10(18) * 211,
+            ));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let result = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(210_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(TOKEN_A),
+                address_bytes(POOL),
+                Arc::new(pool_state()),
+            )
+            .await;
+
+        assert!(matches!(result, Err(EncodeError::PoolMath(_))));
+    }
+
+    #[tokio::test]
+    async fn approves_pool_for_join_when_needed() {
+        const SETTLEMENT: Address = H160([0x50; 20]);
+
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        let token_a = mock.deploy(ERC20::raw_contract().interface.abi.clone());
+        let token_b = mock.deploy(ERC20::raw_contract().interface.abi.clone());
+        let (a, b) = (token_a.address(), token_b.address());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![a, b]);
+        let swap_amount = U256::from(99_999_999_991_934_267_000_u128);
+        let bought = U256::from(90_909_090_902_425_014_050_u128);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .returns(helper_response(b, a, bought, swap_amount));
+        let join_amount_a = U256::from(109_999_999_990_321_120_400_u128);
+        let join_amount_b = U256::from(90_909_090_902_425_014_049_u128);
+        // Token A is approved already, token B only for less than the join.
+        token_a
+            .expect_call(ERC20::signatures().allowance())
+            .once()
+            .predicate_fn(|(owner, spender)| *owner == SETTLEMENT && *spender == POOL)
+            .returns(U256::MAX);
+        token_b
+            .expect_call(ERC20::signatures().allowance())
+            .once()
+            .predicate_fn(|(owner, spender)| *owner == SETTLEMENT && *spender == POOL)
+            .returns(U256::exp10(18));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address())
+            .with_allowance_check(SETTLEMENT);
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(210_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(a),
+                address_bytes(POOL),
+                Arc::new(testing::pool_state(POOL, a, b)),
+            )
+            .await
+            .unwrap();
+
+        let approve = |amount| {
+            interaction_data(&Erc20ApproveInteraction {
+                token: ERC20::at(&mock.web3(), b),
+                spender: POOL,
+                amount,
+            })
+        };
+        let post = &template.post_interactions;
+        assert_eq!(post.len(), 3);
+        assert_eq!(post[0], approve(U256::zero()));
+        assert_eq!(post[1], approve(join_amount_b));
+        assert!(matches!(
+            decode_interaction(&post[2].call_data),
+            Call::JoinPool { max_amounts_in, .. }
+                if max_amounts_in == vec![join_amount_a, join_amount_b]
+        ));
+    }
+
+    #[tokio::test]
+    async fn encodes_buy_order_for_exact_lp_amount() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        // Minting 10% of the supply after buying `x` token B needs
+        // `0.1 * (1000 - x)` of it, so the pool sells `1000 / 11` plus the
+        // rounding tolerance for about 100 token A.
+        let sold = U256::from(90_909_091_000_000_000_002_u128);
+        let bought = U256::from(100_000_000_110_000_000_000_u128);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_sell_amount())
+            .once()
+            .predicate_fn(move |(_, sell_token, sell_amount)| {
+                *sell_token == TOKEN_B && *sell_amount == sold
+            })
+            .returns(helper_response(TOKEN_B, TOKEN_A, sold, bought));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let template = encoder
+            .encode(
+                OrderKind::Buy,
+                BigUint::from(10_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(TOKEN_A),
+                address_bytes(POOL),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+
+        // The join pulls 10% of the balances after the trade.
+        assert_eq!(template.post_interactions.len(), 1);
+        let join = &template.post_interactions[0];
+        assert_eq!(join.target, POOL);
+        assert_eq!(
+            join.call_data,
+            interaction_data(&JoinPoolInteraction {
+                b_cow_pool: BCowPool::at(&mock.web3(), POOL),
+                pool_amount_out: U256::exp10(19),
+                max_amounts_in: vec![
+                    U256::from(110_000_000_011_000_000_000_u128),
+                    U256::from(90_909_090_900_000_000_000_u128),
+                ],
+            })
+            .call_data
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_join_not_covered_by_order() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        // The pool sells less token B than the join needs.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_sell_amount())
+            .returns(helper_response(
+                TOKEN_B,
+                TOKEN_A,
+                U256::exp10(19) * 9,
+                U256::exp10(20),
+            ));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let result = encoder
+            .encode(
+                OrderKind::Buy,
+                BigUint::from(10_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(TOKEN_A),
+                address_bytes(POOL),
+                Arc::new(pool_state()),
+            )
+            .await;
+
+        assert!(matches!(result, Err(EncodeError::PoolMath(_))));
+    }
+
+    #[tokio::test]
+    async fn encodes_buy_order_for_exact_exit_amount() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .predicate_fn(move |(_, buy_token, buy_amount)| {
+                // The exit returns about 51.3 of each token, the pool buys the
+                // token B for the rest of the token A.
+                *buy_token == TOKEN_B
+                    && *buy_amount > U256::exp10(18) * 51
+                    && *buy_amount < U256::exp10(18) * 52
+            })
+            .returns(helper_response(
+                TOKEN_A,
+                TOKEN_B,
+                U256::exp10(18) * 49,
+                U256::exp10(18) * 51,
+            ));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let template = encoder
+            .encode(
+                OrderKind::Buy,
+                BigUint::from(100_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(POOL),
+                address_bytes(TOKEN_A),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(template.pre_interactions.len(), 2);
+        assert_eq!(template.pre_interactions[1].target, POOL);
+        assert_eq!(template.order.sell_token, TOKEN_A);
+        assert_eq!(template.order.buy_token, TOKEN_B);
+    }
+
+    /// Template in which the pool buys `buy_amount` of token B when it has
+    /// `balance` of each token, for a helper response valid until the end of
+    /// time.
+    fn order_on_balances(balance: U256, buy_amount: U256) -> TemplateOrder {
+        let settlement = contracts::deployment(GPv2Settlement::raw_contract(), 1)
+            .unwrap()
+            .address;
+        PoolSnapshot::new(
+            POOL,
+            &[TOKEN_A, TOKEN_B],
+            &[balance, balance],
+            &[U256::exp10(18); 2],
+            DomainSeparator::new(1, settlement),
+        )
+        .unwrap()
+        .order_from_buy_amount(TOKEN_B, buy_amount, (u32::MAX - MAX_ORDER_DURATION).into())
+        .unwrap()
+    }
+
+    /// Whether the pool accepts `order` when it verifies it on `balance_out`
+    /// of the token it sells and `balance_in` of the token it buys.
+    fn pool_accepts(order: &OrderData, balance_out: U256, balance_in: U256) -> bool {
+        let amount_in = bmath::calc_in_given_out(
+            balance_in,
+            U256::exp10(18),
+            balance_out,
+            U256::exp10(18),
+            order.sell_amount,
+            U256::zero(),
+        )
+        .unwrap();
+        amount_in <= order.buy_amount
+    }
+
+    #[tokio::test]
+    async fn prices_exit_swap_after_the_exit() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        // The helper prices the order on the 1000 of each token the pool has
+        // before the exit.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .returns(helper_response(
+                TOKEN_A,
+                TOKEN_B,
+                U256::from(90_909_090_909_090_909_000_u128),
+                U256::exp10(20),
+            ));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(10_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(POOL),
+                address_bytes(TOKEN_A),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+
+        // Exiting 10% of the supply leaves 900 of each token, which the pool
+        // verifies the order on after the exit pre-interaction.
+        let exited = U256::exp10(20) * 9;
+        let order = &template.order;
+        assert_eq!(order.sell_amount, U256::exp10(19) * 9);
+        assert_eq!(order.buy_amount, U256::exp10(20));
+        assert!(pool_accepts(order, exited, exited));
+        assert!(!pool_accepts(
+            &OrderData {
+                sell_amount: U256::from(90_909_090_909_090_909_000_u128),
+                ..order.clone()
+            },
+            exited,
+            exited,
+        ));
+
+        // The commitment is to the repriced order.
+        let expected = order_on_balances(exited, U256::exp10(20));
+        assert_eq!(template.order, expected.order);
+        assert_eq!(template.signature, expected.signature);
+        assert_eq!(template.pre_interactions[0], expected.pre_interactions[0]);
+    }
+
+    #[tokio::test]
+    async fn prices_exit_buy_after_the_exit() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .returns(helper_response(
+                TOKEN_A,
+                TOKEN_B,
+                U256::exp10(18) * 49,
+                U256::exp10(18) * 51,
+            ));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let template = encoder
+            .encode(
+                OrderKind::Buy,
+                BigUint::from(100_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(POOL),
+                address_bytes(TOKEN_A),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+
+        // Exiting for 100 token A burns `1 - sqrt(0.9)` of the supply, which
+        // returns about 51.3 of each token. The pool buys all of the token B
+        // for the missing 48.7 token A.
+        let exit_amount = U256::from(51_316_701_948_471_069_000_u128);
+        let exited = U256::exp10(21) - exit_amount;
+        let missing = U256::exp10(20) - exit_amount;
+        let order = &template.order;
+        assert_eq!(order.buy_amount, exit_amount);
+        assert!(order.sell_amount + rounding_tolerance(missing) >= missing);
+        assert!(order.sell_amount <= missing + rounding_tolerance(missing));
+        let expected = order_on_balances(exited, exit_amount);
+        assert_eq!(template.order, expected.order);
+        assert_eq!(template.pre_interactions[0], expected.pre_interactions[0]);
+    }
+
+    #[tokio::test]
+    async fn applies_slippage_to_join_and_exit() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_sell_amount())
+            .returns(helper_response(
+                TOKEN_B,
+                TOKEN_A,
+                U256::from(90_909_091_000_000_000_002_u128),
+                U256::from(100_000_000_110_000_000_000_u128),
+            ));
+        // The exit returns 100 of each token, of which at least 99.5 token B
+        // are sold to the pool.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .predicate_fn(|(_, buy_token, buy_amount)| {
+                *buy_token == TOKEN_B && *buy_amount == U256::exp10(17) * 995
+            })
+            .returns(helper_response(
+                TOKEN_A,
+                TOKEN_B,
+                U256::exp10(18) * 90,
+                U256::exp10(17) * 995,
+            ));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address())
+            .with_slippage_bps(50);
+        let pool = BCowPool::at(&mock.web3(), POOL);
+
+        // Joining with 10% of the supply needs 110 token A and 90.9 token B.
+        let template = encoder
+            .encode(
+                OrderKind::Buy,
+                BigUint::from(10_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(TOKEN_A),
+                address_bytes(POOL),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(template.slippage_bps, Some(50));
+        assert_eq!(
+            template.post_interactions[0].call_data,
+            interaction_data(&JoinPoolInteraction {
+                b_cow_pool: pool.clone(),
+                pool_amount_out: U256::exp10(19),
+                max_amounts_in: vec![
+                    U256::from(110_550_000_011_055_000_000_u128),
+                    U256::from(91_363_636_354_500_000_000_u128),
+                ],
+            })
+            .call_data
+        );
+
+        // Exiting 10% of the supply returns 100 of each token.
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(10_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(POOL),
+                address_bytes(TOKEN_A),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+        let min_amount_out = U256::exp10(18) * 995 / 10;
+        assert_eq!(template.slippage_bps, Some(50));
+        assert_eq!(
+            template.pre_interactions[1].call_data,
+            interaction_data(&ExitPoolInteraction {
+                b_cow_pool: pool,
+                pool_amount_in: U256::exp10(19),
+                min_amounts_out: vec![min_amount_out, min_amount_out],
+            })
+            .call_data
+        );
+    }
+
+    #[test]
+    fn rounds_slippage_against_the_pool() {
+        let encoder = CowAmmEncoder::with_helper(&contracts::web3::dummy(), 1, POOL)
+            .with_slippage_bps(1);
+        assert_eq!(encoder.max_amount_in(1.into()), 2.into());
+        assert_eq!(encoder.min_amount_out(1.into()), 0.into());
+        assert_eq!(encoder.max_amount_in(U256::MAX), U256::MAX);
+        assert_eq!(encoder.min_amount_out(10_000.into()), 9_999.into());
+
+        let encoder = CowAmmEncoder::with_helper(&contracts::web3::dummy(), 1, POOL);
+        assert_eq!(encoder.max_amount_in(1_000.into()), 1_000.into());
+        assert_eq!(encoder.min_amount_out(1_000.into()), 1_000.into());
+    }
+
+    #[test]
+    fn clamps_slippage_to_100_percent() {
+        let encoder = CowAmmEncoder::with_helper(&contracts::web3::dummy(), 1, POOL)
+            .with_slippage_bps(u32::MAX);
+        assert_eq!(encoder.slippage_bps, MAX_SLIPPAGE_BPS);
+        assert_eq!(encoder.max_amount_in(1_000.into()), 2_000.into());
+        assert_eq!(encoder.min_amount_out(1_000.into()), 0.into());
+    }
+
+    /// Pool state that is not backed by tycho.
+    struct FakePool {
+        tokens: Vec<Address>,
+        balances: Vec<U256>,
+        weights: Vec<U256>,
+    }
+
+    impl FakePool {
+        /// Pool with equal weights for all tokens.
+        fn new(tokens: Vec<Address>, balances: Vec<U256>) -> Self {
+            let weights = vec![U256::exp10(18); tokens.len()];
+            Self {
+                tokens,
+                balances,
+                weights,
+            }
+        }
+    }
+
+    impl PoolState for FakePool {
+        fn address(&self) -> Address {
+            POOL
+        }
+
+        fn lp_token(&self) -> Address {
+            POOL
+        }
+
+        fn lp_supply(&self) -> U256 {
+            U256::exp10(20)
+        }
+
+        fn tokens(&self) -> Vec<Address> {
+            self.tokens.clone()
+        }
+
+        fn balances(&self) -> Vec<U256> {
+            self.balances.clone()
+        }
+
+        fn weights(&self) -> Vec<U256> {
+            self.weights.clone()
+        }
+
+        fn fee(&self) -> U256 {
+            U256::zero()
+        }
+    }
+
+    #[tokio::test]
+    async fn encodes_exit_with_fake_pool_state() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        // Exiting 10% of the supply returns 10% of both balances.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .predicate_fn(|(_, buy_token, buy_amount)| {
+                *buy_token == TOKEN_B && *buy_amount == U256::exp10(20) * 2
+            })
+            .returns(helper_response(TOKEN_A, TOKEN_B, U256::exp10(20), U256::exp10(20) * 2));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let pool = FakePool::new(
+            vec![TOKEN_A, TOKEN_B],
+            vec![U256::exp10(21), U256::exp10(21) * 2],
+        );
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(10_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(POOL),
+                address_bytes(TOKEN_A),
+                Arc::new(pool),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            template.pre_interactions[1].call_data,
+            interaction_data(&ExitPoolInteraction {
+                b_cow_pool: BCowPool::at(&mock.web3(), POOL),
+                pool_amount_in: U256::exp10(19),
+                min_amounts_out: vec![U256::exp10(20), U256::exp10(20) * 2],
+            })
+            .call_data
+        );
+    }
+
+    #[tokio::test]
+    async fn orders_pool_amounts_by_final_tokens() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .returns(helper_response(TOKEN_A, TOKEN_B, U256::exp10(20), U256::exp10(20) * 2));
+
+        // The state lists the tokens in the opposite order of the pool.
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let pool = FakePool::new(
+            vec![TOKEN_B, TOKEN_A],
+            vec![U256::exp10(21) * 2, U256::exp10(21)],
+        );
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(10_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(POOL),
+                address_bytes(TOKEN_A),
+                Arc::new(pool),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            template.pre_interactions[1].call_data,
+            interaction_data(&ExitPoolInteraction {
+                b_cow_pool: BCowPool::at(&mock.web3(), POOL),
+                pool_amount_in: U256::exp10(19),
+                min_amounts_out: vec![U256::exp10(20), U256::exp10(20) * 2],
+            })
+            .call_data
+        );
+    }
+
+    #[tokio::test]
+    async fn sizes_exit_with_pool_weights() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .returns(helper_response(
+                TOKEN_A,
+                TOKEN_B,
+                U256::exp10(18) * 20,
+                U256::exp10(18) * 80,
+            ));
+
+        // 80/20 pool, so most of the exit value is in token A already.
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let weights = vec![U256::exp10(18) * 4, U256::exp10(18)];
+        let pool = Arc::new(FakePool {
+            weights: weights.clone(),
+            ..FakePool::new(vec![TOKEN_A, TOKEN_B], vec![U256::exp10(21); 2])
+        });
+        let template = encoder
+            .encode(
+                OrderKind::Buy,
+                BigUint::from(100_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(POOL),
+                address_bytes(TOKEN_A),
+                pool.clone(),
+            )
+            .await
+            .unwrap();
+
+        let lp_amount_in = sizing::lp_in_for_exact_token_out(
+            U256::exp10(20),
+            U256::exp10(21),
+            weights[0],
+            total_weight(&weights),
+            U256::exp10(20),
+        )
+        .unwrap();
+        // `1 - 0.9 ^ 0.8` of the supply instead of `1 - 0.9 ^ 0.5`.
+        assert!(lp_amount_in > U256::exp10(18) * 8 && lp_amount_in < U256::exp10(18) * 9);
+        let exit_amounts = pool.exit_amounts_out(lp_amount_in).unwrap();
+        assert_eq!(
+            template.pre_interactions[1].call_data,
+            interaction_data(&ExitPoolInteraction {
+                b_cow_pool: BCowPool::at(&mock.web3(), POOL),
+                pool_amount_in: lp_amount_in,
+                min_amounts_out: exit_amounts,
+            })
+            .call_data
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_pools_with_more_than_two_tokens() {
+        // The helper reverts for such pools, so it is not queried.
+        let encoder = CowAmmEncoder::with_helper(&contracts::web3::dummy(), 1, POOL);
+        let token_c = H160([0xcc; 20]);
+        let pool: Arc<dyn PoolState> = Arc::new(FakePool::new(
+            vec![TOKEN_A, TOKEN_B, token_c],
+            vec![U256::exp10(21); 3],
+        ));
+
+        for (token_in, token_out) in [(TOKEN_A, token_c), (TOKEN_A, POOL), (POOL, TOKEN_A)] {
+            let result = encoder
+                .encode(
+                    OrderKind::Sell,
+                    BigUint::from(1_000_u32),
+                    address_bytes(token_in),
+                    address_bytes(token_out),
+                    pool.clone(),
+                )
+                .await;
+            assert!(matches!(result, Err(EncodeError::TooManyTokens(3))));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_pools_with_a_single_token() {
+        let encoder = CowAmmEncoder::with_helper(&contracts::web3::dummy(), 1, POOL);
+        let pool = FakePool::new(vec![TOKEN_A], vec![U256::exp10(21)]);
+        let result = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(TOKEN_A),
+                address_bytes(TOKEN_B),
+                Arc::new(pool),
+            )
+            .await;
+
+        assert!(matches!(result, Err(EncodeError::UnsupportedPoolState)));
+    }
+
+    #[tokio::test]
+    async fn wraps_native_eth_sold_to_pool() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![WETH, TOKEN_B]);
+        // The pool buys the 1000 WETH the settlement pays and sells 990
+        // token B for it.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .predicate_fn(|(_, buy_token, buy_amount)| {
+                *buy_token == WETH && *buy_amount == 1_000.into()
+            })
+            .returns(helper_response(TOKEN_B, WETH, 990.into(), 1_000.into()));
+
+        let encoder =
+            CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address()).with_weth(WETH);
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(BUY_ETH_ADDRESS),
+                address_bytes(TOKEN_B),
+                Arc::new(testing::pool_state(POOL, WETH, TOKEN_B)),
+            )
+            .await
+            .unwrap();
+
+        // Only the ETH the pool's order buys is wrapped, before the commit.
+        assert_eq!(template.order.sell_token, TOKEN_B);
+        assert_eq!(template.order.buy_token, WETH);
+        assert_eq!(template.pre_interactions.len(), 2);
+        assert_eq!(
+            template.pre_interactions[0],
+            interaction_data(&WethDepositInteraction {
+                weth: WETH9::at(&mock.web3(), WETH),
+                amount: template.order.buy_amount,
+            })
+        );
+        assert!(template.post_interactions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn wraps_native_eth_joined_into_pool() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![WETH, TOKEN_B]);
+        // Same join as in `sizes_single_sided_join`, with ETH instead of
+        // token A.
+        let swap_amount = U256::from(99_999_999_991_934_267_000_u128);
+        let bought = U256::from(90_909_090_902_425_014_050_u128);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .returns(helper_response(TOKEN_B, WETH, bought, swap_amount));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address())
+            .with_weth(WETH)
+            .with_slippage_bps(100);
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(210_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(BUY_ETH_ADDRESS),
+                address_bytes(POOL),
+                Arc::new(testing::pool_state(POOL, WETH, TOKEN_B)),
+            )
+            .await
+            .unwrap();
+
+        // The ETH the order and the join use is wrapped, not the maximum
+        // the join may pull after slippage.
+        let join_amount = U256::from(109_999_999_990_321_120_400_u128);
+        assert_eq!(
+            template.pre_interactions[0],
+            interaction_data(&WethDepositInteraction {
+                weth: WETH9::at(&mock.web3(), WETH),
+                amount: swap_amount + join_amount,
+            })
+        );
+        let Call::JoinPool { max_amounts_in, .. } =
+            decode_interaction(&template.post_interactions.last().unwrap().call_data)
+        else {
+            panic!("last post-interaction is not a join");
+        };
+        assert!(max_amounts_in[0] > join_amount);
+    }
+
+    #[tokio::test]
+    async fn unwraps_native_eth_bought_from_pool() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![WETH, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_sell_amount())
+            .once()
+            .predicate_fn(|(_, sell_token, _)| *sell_token == WETH)
+            .returns(helper_response(WETH, TOKEN_B, 990.into(), 1_000.into()));
+
+        let encoder =
+            CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address()).with_weth(WETH);
+        let template = encoder
+            .encode(
+                OrderKind::Buy,
+                BigUint::from(990_u32),
+                address_bytes(TOKEN_B),
+                address_bytes(BUY_ETH_ADDRESS),
+                Arc::new(testing::pool_state(POOL, WETH, TOKEN_B)),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(template.order.sell_token, WETH);
+        assert_eq!(template.pre_interactions.len(), 1);
+        assert_eq!(
+            template.post_interactions,
+            vec![interaction_data(&WethWithdrawInteraction {
+                weth: WETH9::at(&mock.web3(), WETH),
+                amount: 990.into(),
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn unwraps_weth_returned_by_exit() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![WETH, TOKEN_B]);
+        // Exiting with 10 of the 100 LP tokens returns 100 WETH and 100
+        // token B, which is sold for more WETH.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .returns(helper_response(
+                WETH,
+                TOKEN_B,
+                U256::exp10(19) * 9,
+                U256::exp10(18) * 99,
+            ));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address())
+            .with_weth(WETH)
+            .with_slippage_bps(100);
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(10_u32) * BigUint::from(10_u32).pow(18),
+                address_bytes(POOL),
+                address_bytes(BUY_ETH_ADDRESS),
+                Arc::new(testing::pool_state(POOL, WETH, TOKEN_B)),
+            )
+            .await
+            .unwrap();
+
+        // The exit returns at least 99 WETH after slippage, and the pool sells
+        // 89.2 WETH for the 99 token B on the 900 of each token it has left.
+        let withdraw = template.post_interactions.last().unwrap();
+        assert_eq!(withdraw.target, WETH);
+        assert_eq!(
+            decode_interaction(&withdraw.call_data),
+            Call::WethWithdraw(
+                U256::exp10(18) * 99 + U256::from(89_189_189_189_189_189_100_u128)
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn requires_weth_for_native_eth() {
+        let encoder = CowAmmEncoder::with_helper(&contracts::web3::dummy(), 1337, POOL);
+        let result = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(BUY_ETH_ADDRESS),
+                address_bytes(TOKEN_B),
+                Arc::new(testing::pool_state(POOL, WETH, TOKEN_B)),
+            )
+            .await;
+
+        assert!(matches!(result, Err(EncodeError::MissingWeth(1337))));
+    }
+
+    /// Validator that records the checks it was asked to perform.
+    struct RecordingValidator {
+        valid: bool,
+        checks: std::sync::Mutex<Vec<SignatureCheck>>,
+    }
+
+    #[async_trait::async_trait]
+    impl SignatureValidating for RecordingValidator {
+        async fn validate_signature_and_get_additional_gas(
+            &self,
+            check: SignatureCheck,
+        ) -> Result<u64, SignatureValidationError> {
+            self.checks.lock().unwrap().push(check);
+            match self.valid {
+                true => Ok(0),
+                false => Err(SignatureValidationError::Invalid),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn validates_signature_including_exit_interaction() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .returns(helper_response(TOKEN_A, TOKEN_B, 9_900.into(), 10_000.into()));
+
+        let validator = Arc::new(RecordingValidator {
+            valid: true,
+            checks: Default::default(),
+        });
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address())
+            .with_signature_validation(validator.clone(), DomainSeparator([0x01; 32]));
+        let template = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(POOL),
+                address_bytes(TOKEN_A),
+                Arc::new(pool_state()),
+            )
+            .await
+            .unwrap();
+
+        let checks = validator.checks.lock().unwrap();
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].signer, POOL);
+        assert_eq!(checks[0].signature, template.signature.to_bytes());
+        // The commitment to the order followed by the appended pool exit.
+        assert_eq!(checks[0].interactions, template.pre_interactions);
+        assert_eq!(checks[0].interactions.len(), 2);
+        assert_eq!(checks[0].interactions[1].target, POOL);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_signature() {
+        let mock = Mock::new(1);
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .returns(vec![TOKEN_A, TOKEN_B]);
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .returns(helper_response(TOKEN_B, TOKEN_A, 990.into(), 1_000.into()));
+
+        let validator = Arc::new(RecordingValidator {
+            valid: false,
+            checks: Default::default(),
+        });
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address())
+            .with_signature_validation(validator, DomainSeparator([0x01; 32]));
+        let result = encoder
+            .encode(
+                OrderKind::Sell,
+                BigUint::from(1_000_u32),
+                address_bytes(TOKEN_A),
+                address_bytes(TOKEN_B),
+                Arc::new(pool_state()),
+            )
+            .await;
+
+        assert!(matches!(result, Err(EncodeError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn resolves_helper_for_chain() {
+        let encoder = CowAmmEncoder::new(&contracts::web3::dummy(), 1).unwrap();
+        assert_eq!(
+            encoder.helper().address(),
+            H160(hex!("03362f847b4fabc12e1ce98b6b59f94401e4588e"))
+        );
+
+        let encoder = CowAmmEncoder::new(&contracts::web3::dummy(), 100).unwrap();
+        assert_eq!(
+            encoder.helper().address(),
+            H160(hex!("db2aeab529c035469e190310def9957ef0398ba8"))
+        );
+    }
+
+    #[test]
+    fn overrides_helper_address() {
+        let helper = H160([0x42; 20]);
+        let encoder = CowAmmEncoder::with_helper(&contracts::web3::dummy(), 31337, helper);
+        assert_eq!(encoder.helper().address(), helper);
+        assert_eq!(encoder.chain_id(), 31337);
+    }
+
+    #[test]
+    fn missing_helper_deployment() {
+        let result = CowAmmEncoder::new(&contracts::web3::dummy(), 31337);
+        assert!(matches!(result, Err(MissingHelperDeployment(31337))));
+    }
+}
@@ -0,0 +1,242 @@
+use {
+    contracts::{
+        cow_amm_revert::CowAmmRevert,
+        errors::{revert_data, EthcontractErrorType},
+    },
+    cow_amm::local_helper::HelperError,
+    ethcontract::{errors::MethodError, Address, U256},
+};
+
+/// Reasons why a CoW AMM swap could not be encoded.
+///
+/// The variants are grouped so that callers can decide how to react: node
+/// failures are usually worth retrying, pool and helper errors mean the pool
+/// should be skipped for now and invalid input points at a bug in the caller.
+#[derive(Debug, thiserror::Error)]
+pub enum EncodeError {
+    /// The pool state describes a pool the encoder can not handle.
+    #[error("unsupported pool state")]
+    UnsupportedPoolState,
+    /// The pool trades more tokens than the `BCowHelper`, which only creates
+    /// orders for pools with two tokens, supports.
+    #[error("pool trades {0} tokens but the helper only supports two")]
+    TooManyTokens(usize),
+    /// An address did not have the expected length of 20 bytes.
+    #[error("invalid address length: expected 20 bytes, got {0}")]
+    InvalidAddress(usize),
+    /// The token is neither traded by the pool nor its LP token.
+    #[error("token {0:?} is not traded by the pool")]
+    UnknownToken(Address),
+    /// Native ETH was requested but no WETH contract is known to wrap it.
+    #[error("no WETH contract to wrap ETH on chain {0}")]
+    MissingWeth(u64),
+    /// No settlement contract is known on the chain to sign the orders of
+    /// pool exits for.
+    #[error("no GPv2Settlement deployment on chain {0}")]
+    MissingSettlement(u64),
+    /// Both the input and the output token are the LP token of the pool.
+    #[error("cannot swap LP token for LP token")]
+    LpToLp,
+    /// The helper reports different tokens for the pool than the pool state.
+    #[error("pool {pool:?} trades {actual:?} but its state describes {expected:?}")]
+    PoolTokenMismatch {
+        pool: Address,
+        actual: Vec<Address>,
+        expected: Vec<Address>,
+    },
+    /// The off-chain pool math failed for the requested amounts.
+    #[error("pool math failed: {0}")]
+    PoolMath(String),
+    /// The helper found no order that rebalances the pool as requested.
+    #[error("helper reverted with NoOrder")]
+    NoOrder,
+    /// The pool is paused.
+    #[error("helper reverted with PoolIsPaused")]
+    PoolIsPaused,
+    /// The pool is closed.
+    #[error("helper reverted with PoolIsClosed")]
+    PoolIsClosed,
+    /// The helper does not accept the token for the pool.
+    #[error("helper reverted with InvalidToken")]
+    InvalidToken,
+    /// The helper does not know the pool.
+    #[error("helper reverted with PoolDoesNotExist")]
+    PoolDoesNotExist,
+    /// The helper or pool reverted with another known custom error.
+    #[error("contract reverted with {0}")]
+    Revert(CowAmmRevert),
+    /// Communicating with the node failed.
+    #[error("node error: {0}")]
+    Node(MethodError),
+    /// The call reached the contract but failed for an unrecognized reason.
+    #[error("contract error: {0}")]
+    Contract(MethodError),
+    /// The signature of the encoded template failed validation.
+    #[error("invalid template signature: {0:#}")]
+    InvalidSignature(anyhow::Error),
+    /// The helper returned an order that could not be converted.
+    #[error("invalid helper response: {0:#}")]
+    InvalidHelperResponse(anyhow::Error),
+}
+
+impl EncodeError {
+    /// Converts an error returned by the `Amm` helper bindings.
+    pub(crate) fn from_helper(err: anyhow::Error) -> Self {
+        match err.downcast::<MethodError>() {
+            Ok(err) => err.into(),
+            Err(err) => Self::InvalidHelperResponse(err),
+        }
+    }
+}
+
+impl From<MethodError> for EncodeError {
+    fn from(err: MethodError) -> Self {
+        match CowAmmRevert::from_method_error(&err) {
+            Some(CowAmmRevert::NoOrder) => Self::NoOrder,
+            Some(CowAmmRevert::PoolIsPaused) => Self::PoolIsPaused,
+            Some(CowAmmRevert::PoolIsClosed) => Self::PoolIsClosed,
+            Some(CowAmmRevert::InvalidToken) => Self::InvalidToken,
+            Some(CowAmmRevert::PoolDoesNotExist) => Self::PoolDoesNotExist,
+            Some(revert) => Self::Revert(revert),
+            // Revert data means the node executed the call, even if it
+            // reported the revert as an RPC error.
+            None if revert_data(&err).is_some() => Self::Contract(err),
+            None => match EthcontractErrorType::classify(&err) {
+                EthcontractErrorType::Node => Self::Node(err),
+                EthcontractErrorType::Contract => Self::Contract(err),
+            },
+        }
+    }
+}
+
+impl From<HelperError> for EncodeError {
+    fn from(err: HelperError) -> Self {
+        match err {
+            HelperError::InvalidToken => Self::InvalidToken,
+            HelperError::PoolDoesNotExist => Self::PoolDoesNotExist,
+            err => Self::PoolMath(err.to_string()),
+        }
+    }
+}
+
+/// Reasons why a route across several CoW AMMs could not be encoded.
+#[derive(Debug, thiserror::Error)]
+pub enum RouteError {
+    /// The route does not contain any hops.
+    #[error("route has no hops")]
+    Empty,
+    /// The hop does not start with the token the previous hop ends with.
+    #[error("hop {0} does not continue the previous hop")]
+    Disconnected(usize),
+    /// The hop trades the LP token of its pool. Routes only swap between the
+    /// tokens traded by the pools.
+    #[error("hop {0} trades the LP token of its pool")]
+    LpToken(usize),
+    /// A pool can only provide one order per settlement.
+    #[error("pool {0:?} is used by more than one hop")]
+    DuplicatePool(Address),
+    /// Encoding a single hop failed.
+    #[error("failed to encode hop {hop}: {source}")]
+    Encode {
+        hop: usize,
+        #[source]
+        source: EncodeError,
+    },
+}
+
+/// Reasons why a settlement could not be encoded.
+#[derive(Debug, thiserror::Error)]
+pub enum SettlementError {
+    /// A trade uses a token without a clearing price.
+    #[error("missing clearing price for token {0:?}")]
+    MissingPrice(Address),
+}
+
+/// Reasons why `settle` calldata could not be decoded.
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    /// The calldata does not call `settle`.
+    #[error("calldata does not call settle")]
+    NotSettle,
+    /// The arguments do not match the signature of `settle`.
+    #[error("invalid settle arguments: {0}")]
+    Abi(String),
+    /// A trade references a token that is not in the token list.
+    #[error("trade {trade} references unknown token index {token_index}")]
+    InvalidTokenIndex { trade: usize, token_index: U256 },
+    /// The signature of a trade is malformed for its signing scheme.
+    #[error("trade {0} has an invalid signature")]
+    InvalidSignature(usize),
+}
+
+/// Reasons why a settlement could not be simulated or failed in the
+/// simulation.
+#[derive(Debug, thiserror::Error)]
+pub enum SimulationError {
+    /// Only template orders signed with EIP-1271 can be simulated.
+    #[error("template order is not signed with EIP-1271")]
+    UnsupportedSignature,
+    /// The snapshot has no code for a contract the settlement needs.
+    #[error("state snapshot has no code for {0:?}")]
+    MissingCode(Address),
+    /// Reading a balance of the token failed.
+    #[error("token {0:?} does not implement balanceOf")]
+    InvalidToken(Address),
+    /// The settlement reverted, with the decoded reason if known.
+    #[error("settlement reverted: {0}")]
+    Reverted(String),
+    /// The settlement halted, e.g. because it ran out of gas.
+    #[error("settlement halted: {0}")]
+    Halted(String),
+    /// The EVM rejected a transaction, e.g. because of an invalid nonce.
+    #[error("evm error: {0}")]
+    Evm(String),
+    /// Recording a snapshot from a node failed.
+    #[error("node error: {0}")]
+    Node(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        contracts::errors::{testing_contract_error, testing_node_error, testing_revert_error},
+    };
+
+    #[test]
+    fn classifies_helper_reverts() {
+        let err = EncodeError::from(testing_revert_error(&[0x19, 0xaa, 0xd5, 0x73]));
+        assert!(matches!(err, EncodeError::NoOrder));
+        let err = EncodeError::from(testing_revert_error(&[0x21, 0x08, 0x1a, 0xbf]));
+        assert!(matches!(err, EncodeError::PoolIsPaused));
+        let err = EncodeError::from(testing_revert_error(&[0xef, 0xc8, 0x69, 0xb4]));
+        assert!(matches!(err, EncodeError::PoolIsClosed));
+        let err = EncodeError::from(testing_revert_error(&[0xc1, 0xab, 0x6d, 0xc1]));
+        assert!(matches!(err, EncodeError::InvalidToken));
+        let err = EncodeError::from(testing_revert_error(&[0x20, 0x2e, 0x37, 0x7f]));
+        assert!(matches!(err, EncodeError::Revert(CowAmmRevert::BNum_MulOverflow)));
+        let err = EncodeError::from(testing_revert_error(&[0xde, 0xad, 0xbe, 0xef]));
+        assert!(matches!(err, EncodeError::Contract(_)));
+    }
+
+    #[test]
+    fn classifies_method_errors() {
+        assert!(matches!(
+            EncodeError::from(testing_node_error()),
+            EncodeError::Node(_)
+        ));
+        assert!(matches!(
+            EncodeError::from(testing_contract_error()),
+            EncodeError::Contract(_)
+        ));
+    }
+
+    #[test]
+    fn unwraps_helper_errors() {
+        let err = EncodeError::from_helper(testing_node_error().into());
+        assert!(matches!(err, EncodeError::Node(_)));
+
+        let err = EncodeError::from_helper(anyhow::anyhow!("unknown order type"));
+        assert!(matches!(err, EncodeError::InvalidHelperResponse(_)));
+    }
+}
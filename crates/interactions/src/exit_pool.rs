@@ -1,14 +1,8 @@
 use {
-    ethcontract::{
-        tokens::Bytes,
-        H256
-    },
+    ethcontract::tokens::Bytes,
     primitive_types::U256,
-        contracts::{BCowPool},
-        shared::{
-            interaction::{EncodedInteraction, Interaction},
-            http_solver::model::TokenAmount
-    },
+    contracts::BCowPool,
+    shared::interaction::{EncodedInteraction, Interaction},
 };
 
 #[derive(Clone, Debug)]
@@ -42,20 +36,20 @@ mod tests {
     use primitive_types::{H160};
 
     #[test]
-    fn encode_join_pool() {
+    fn encode_exit_pool() {
         let b_cow_pool = dummy_contract!(BCowPool, [0x01; 20]); 
         
         let interaction = ExitPoolInteraction {
-            b_cow_pool: b_cow_pool,
+            b_cow_pool,
             pool_amount_in: U256::from_dec_str("1000000000000000000").unwrap(), // 1e18
-            max_amounts_out: vec![
+            min_amounts_out: vec![
                 U256::from_dec_str("500000000000000000").unwrap(), // 0.5e18
                 U256::from_dec_str("500000000000000000").unwrap(), // 0.5e18
             ],
         };
 
         let (to, value, data) = interaction.encode();
-        assert_eq!(to, vault.address());
+        assert_eq!(to, H160([0x01; 20]));
         assert_eq!(value, U256::zero());
         assert!(data.0.len() > 4); // basic sanity check
     }
@@ -1,15 +1,8 @@
 use {
-    ethcontract::{
-        tokens::Bytes,
-        H256,
-        Address
-    },
+    ethcontract::tokens::Bytes,
     primitive_types::U256,
     contracts::BCowPool,
-    shared::{
-        http_solver::model::TokenAmount,
-        interaction::{EncodedInteraction, Interaction},
-    },
+    shared::interaction::{EncodedInteraction, Interaction},
 };
 
 #[derive(Clone, Debug)]
@@ -49,7 +42,7 @@ mod tests {
         let b_cow_pool = dummy_contract!(BCowPool, [0x01; 20]);
 
         let interaction = JoinPoolInteraction {
-            b_cow_pool: b_cow_pool,
+            b_cow_pool,
             pool_amount_out: U256::from_dec_str("1000000000000000000").unwrap(), // 1e18
             max_amounts_in: vec![
                 U256::from_dec_str("500000000000000000").unwrap(), // 0.5e18
@@ -58,7 +51,7 @@ mod tests {
         };
 
         let (to, value, data) = interaction.encode();
-        assert_eq!(to, b_cow_pool);
+        assert_eq!(to, H160([0x01; 20]));
         assert_eq!(value, U256::zero());
         assert!(data.0.len() > 4); // basic sanity check
     }
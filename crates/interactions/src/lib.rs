@@ -1,5 +1,18 @@
+pub mod approve;
 pub mod join_pool;
 pub mod exit_pool;
+pub mod pool_swap;
 pub mod encode_cowamm;
+pub mod decode_settlement;
+pub mod errors;
+pub mod sizing;
+pub mod pool_state;
+pub mod route;
+pub mod settlement;
+pub mod simulation;
+pub mod weth;
 
-//services/crates/solver/src/interactions/
\ No newline at end of file
+#[cfg(test)]
+mod testing;
+
+//services/crates/solver/src/interactions/
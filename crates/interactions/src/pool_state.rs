@@ -0,0 +1,141 @@
+use {
+    crate::{
+        encode_cowamm::{alloy_to_ethcontract, ethcontract_to_alloy},
+        sizing,
+    },
+    anyhow::Result,
+    ethcontract::{Address, U256},
+    tycho_simulation::{evm::protocol::cowamm::state::CowAMMState, tycho_common::Bytes},
+};
+
+/// State of a CoW AMM that the encoder sizes swaps, joins and exits with.
+///
+/// Implemented for tycho's [`CowAMMState`]. Other state sources, like an
+/// indexer or fakes in tests, can implement it to be used with the encoder.
+/// Balances, weights and join or exit amounts are in [`PoolState::tokens`]
+/// order, which does not need to match the pool's `getFinalTokens` order.
+pub trait PoolState: Send + Sync {
+    /// Address of the pool.
+    fn address(&self) -> Address;
+
+    /// Token minted when joining and burned when exiting the pool.
+    fn lp_token(&self) -> Address;
+
+    /// Total supply of the LP token.
+    fn lp_supply(&self) -> U256;
+
+    /// Tokens traded by the pool.
+    fn tokens(&self) -> Vec<Address>;
+
+    /// Balances of the pool tokens.
+    fn balances(&self) -> Vec<U256>;
+
+    /// Denormalized weights of the pool tokens.
+    fn weights(&self) -> Vec<U256>;
+
+    /// Swap fee as an 18 decimal fixed-point number.
+    fn fee(&self) -> U256;
+
+    /// Amounts of every token `joinPool` pulls for minting `lp_amount_out`.
+    fn join_amounts_in(&self, lp_amount_out: U256) -> Result<Vec<U256>> {
+        proportional_amounts(self, lp_amount_out)
+    }
+
+    /// Amounts of every token `exitPool` returns for burning `lp_amount_in`.
+    fn exit_amounts_out(&self, lp_amount_in: U256) -> Result<Vec<U256>> {
+        proportional_amounts(self, lp_amount_in)
+    }
+}
+
+/// Share of every balance that corresponds to `lp_amount`, rounded like the
+/// pool does.
+fn proportional_amounts<S>(state: &S, lp_amount: U256) -> Result<Vec<U256>>
+where
+    S: PoolState + ?Sized,
+{
+    Ok(sizing::proportional_amounts(
+        lp_amount,
+        state.lp_supply(),
+        &state.balances(),
+    )?)
+}
+
+// Tycho only indexes EVM pools, so the addresses of the state always have 20
+// bytes.
+impl PoolState for CowAMMState {
+    fn address(&self) -> Address {
+        to_address(&self.address)
+    }
+
+    fn lp_token(&self) -> Address {
+        to_address(&self.lp_token)
+    }
+
+    fn lp_supply(&self) -> U256 {
+        alloy_to_ethcontract(self.lp_token_supply)
+    }
+
+    fn tokens(&self) -> Vec<Address> {
+        vec![to_address(&self.token_a.0), to_address(&self.token_b.0)]
+    }
+
+    fn balances(&self) -> Vec<U256> {
+        vec![
+            alloy_to_ethcontract(self.token_a.1),
+            alloy_to_ethcontract(self.token_b.1),
+        ]
+    }
+
+    fn weights(&self) -> Vec<U256> {
+        vec![
+            alloy_to_ethcontract(self.token_a.2),
+            alloy_to_ethcontract(self.token_b.2),
+        ]
+    }
+
+    fn fee(&self) -> U256 {
+        U256::from(self.fee)
+    }
+
+    fn exit_amounts_out(&self, lp_amount_in: U256) -> Result<Vec<U256>> {
+        let (amount_a, amount_b) = self
+            .calc_tokens_out_given_exact_lp_token_in(ethcontract_to_alloy(lp_amount_in))
+            .map_err(|err| anyhow::anyhow!("{err}"))?;
+        Ok(vec![
+            alloy_to_ethcontract(amount_a),
+            alloy_to_ethcontract(amount_b),
+        ])
+    }
+}
+
+fn to_address(bytes: &Bytes) -> Address {
+    Address::from_slice(bytes.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::testing::pool_state, ethcontract::H160};
+
+    #[test]
+    fn reads_tycho_state() {
+        let state = pool_state(H160([0x11; 20]), H160([0xaa; 20]), H160([0xbb; 20]));
+        let state: &dyn PoolState = &state;
+        assert_eq!(state.address(), H160([0x11; 20]));
+        assert_eq!(state.lp_token(), H160([0x11; 20]));
+        assert_eq!(state.tokens(), vec![H160([0xaa; 20]), H160([0xbb; 20])]);
+        assert_eq!(state.balances(), vec![U256::exp10(21), U256::exp10(21)]);
+        assert_eq!(state.weights(), vec![U256::exp10(18), U256::exp10(18)]);
+        assert_eq!(state.lp_supply(), U256::exp10(20));
+        assert_eq!(state.fee(), U256::zero());
+    }
+
+    #[test]
+    fn computes_proportional_amounts() {
+        let state = pool_state(H160([0x11; 20]), H160([0xaa; 20]), H160([0xbb; 20]));
+        let state: &dyn PoolState = &state;
+        // 10% of the supply is worth 10% of every balance.
+        let amounts = vec![U256::exp10(20), U256::exp10(20)];
+        assert_eq!(state.join_amounts_in(U256::exp10(19)).unwrap(), amounts);
+        assert_eq!(state.exit_amounts_out(U256::exp10(19)).unwrap(), amounts);
+    }
+}
@@ -0,0 +1,96 @@
+use {
+    ethcontract::{tokens::Bytes, Address},
+    primitive_types::U256,
+    contracts::BCowPool,
+    shared::interaction::{EncodedInteraction, Interaction},
+};
+
+/// Swaps exactly `token_amount_in` of the settlement contract's `token_in`
+/// for at least `min_amount_out` of `token_out` through the pool.
+#[derive(Clone, Debug)]
+pub struct SwapExactAmountInInteraction {
+    pub b_cow_pool: BCowPool,
+    pub token_in: Address,
+    pub token_amount_in: U256,
+    pub token_out: Address,
+    pub min_amount_out: U256,
+    pub max_price: U256,
+}
+
+impl Interaction for SwapExactAmountInInteraction {
+    fn encode(&self) -> EncodedInteraction {
+        let method = self.b_cow_pool.swap_exact_amount_in(
+            self.token_in,
+            self.token_amount_in,
+            self.token_out,
+            self.min_amount_out,
+            self.max_price,
+        );
+        let calldata = method.tx.data.expect("no calldata").0;
+        (self.b_cow_pool.address(), 0.into(), Bytes(calldata))
+    }
+}
+
+/// Swaps at most `max_amount_in` of the settlement contract's `token_in` for
+/// exactly `token_amount_out` of `token_out` through the pool.
+#[derive(Clone, Debug)]
+pub struct SwapExactAmountOutInteraction {
+    pub b_cow_pool: BCowPool,
+    pub token_in: Address,
+    pub max_amount_in: U256,
+    pub token_out: Address,
+    pub token_amount_out: U256,
+    pub max_price: U256,
+}
+
+impl Interaction for SwapExactAmountOutInteraction {
+    fn encode(&self) -> EncodedInteraction {
+        let method = self.b_cow_pool.swap_exact_amount_out(
+            self.token_in,
+            self.max_amount_in,
+            self.token_out,
+            self.token_amount_out,
+            self.max_price,
+        );
+        let calldata = method.tx.data.expect("no calldata").0;
+        (self.b_cow_pool.address(), 0.into(), Bytes(calldata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, contracts::dummy_contract, primitive_types::H160};
+
+    #[test]
+    fn encode_swaps() {
+        let b_cow_pool = dummy_contract!(BCowPool, [0x01; 20]);
+
+        let (to, value, data) = SwapExactAmountInInteraction {
+            b_cow_pool: b_cow_pool.clone(),
+            token_in: H160([0x02; 20]),
+            token_amount_in: 1_000.into(),
+            token_out: H160([0x03; 20]),
+            min_amount_out: 990.into(),
+            max_price: U256::MAX,
+        }
+        .encode();
+        assert_eq!(to, H160([0x01; 20]));
+        assert_eq!(value, U256::zero());
+        // swapExactAmountIn(address,uint256,address,uint256,uint256)
+        assert_eq!(data.0[..4], [0x82, 0x01, 0xaa, 0x3f]);
+
+        let (to, value, data) = SwapExactAmountOutInteraction {
+            b_cow_pool,
+            token_in: H160([0x02; 20]),
+            max_amount_in: 1_010.into(),
+            token_out: H160([0x03; 20]),
+            token_amount_out: 1_000.into(),
+            max_price: U256::MAX,
+        }
+        .encode();
+        assert_eq!(to, H160([0x01; 20]));
+        assert_eq!(value, U256::zero());
+        // swapExactAmountOut(address,uint256,address,uint256,uint256)
+        assert_eq!(data.0[..4], [0x7c, 0x5e, 0x9e, 0xa4]);
+    }
+}
@@ -0,0 +1,288 @@
+use {
+    crate::{
+        encode_cowamm::{bytes_to_address, traded_amount, CowAmmEncoder, PoolState},
+        errors::RouteError,
+    },
+    cow_amm::helper::TemplateOrder,
+    ethcontract::U256,
+    model::{interaction::InteractionData, order::OrderKind},
+    num_bigint::BigUint,
+    std::{collections::HashSet, sync::Arc},
+    tycho_simulation::tycho_common::Bytes,
+};
+
+/// A swap through a single CoW AMM as part of a route.
+#[derive(Clone)]
+pub struct Hop {
+    pub token_in: Bytes,
+    pub token_out: Bytes,
+    pub pool_state: Arc<dyn PoolState>,
+}
+
+/// Template orders for every hop of a route together with the interactions
+/// needed to execute all of them in a single settlement.
+pub struct EncodedRoute {
+    /// One template order per hop, in route order.
+    pub templates: Vec<TemplateOrder>,
+    /// Pre-interactions of all templates, in route order.
+    pub pre_interactions: Vec<InteractionData>,
+    /// Post-interactions of all templates, in route order.
+    pub post_interactions: Vec<InteractionData>,
+}
+
+impl CowAmmEncoder {
+    /// Encodes a route of swaps through different CoW AMMs, e.g.
+    /// COW -> WETH -> wstETH, with one template order per pool.
+    ///
+    /// For sell orders `amount` is sold into the first hop and every following
+    /// hop sells what the previous one bought. For buy orders `amount` is
+    /// bought from the last hop and every preceding hop buys what the next one
+    /// sells.
+    pub async fn encode_route(
+        &self,
+        kind: OrderKind,
+        amount: BigUint,
+        hops: &[Hop],
+    ) -> Result<EncodedRoute, RouteError> {
+        validate_route(hops)?;
+
+        let mut templates = Vec::with_capacity(hops.len());
+        let mut amount = amount;
+        match kind {
+            OrderKind::Sell => {
+                for (i, hop) in hops.iter().enumerate() {
+                    let template = self.encode_hop(i, kind, amount, hop).await?;
+                    amount = hop_amount(i, &template, &hop.token_out)?;
+                    templates.push(template);
+                }
+            }
+            OrderKind::Buy => {
+                for (i, hop) in hops.iter().enumerate().rev() {
+                    let template = self.encode_hop(i, kind, amount, hop).await?;
+                    amount = hop_amount(i, &template, &hop.token_in)?;
+                    templates.push(template);
+                }
+                templates.reverse();
+            }
+        }
+
+        let pre_interactions = templates
+            .iter()
+            .flat_map(|template| template.pre_interactions.iter().cloned())
+            .collect();
+        let post_interactions = templates
+            .iter()
+            .flat_map(|template| template.post_interactions.iter().cloned())
+            .collect();
+        Ok(EncodedRoute {
+            templates,
+            pre_interactions,
+            post_interactions,
+        })
+    }
+
+    async fn encode_hop(
+        &self,
+        index: usize,
+        kind: OrderKind,
+        amount: BigUint,
+        hop: &Hop,
+    ) -> Result<TemplateOrder, RouteError> {
+        self.encode(
+            kind,
+            amount,
+            hop.token_in.clone(),
+            hop.token_out.clone(),
+            hop.pool_state.clone(),
+        )
+        .await
+        .map_err(|source| RouteError::Encode { hop: index, source })
+    }
+}
+
+/// Checks that the hops form a connected route through distinct pools that
+/// only swaps between pool tokens.
+fn validate_route(hops: &[Hop]) -> Result<(), RouteError> {
+    if hops.is_empty() {
+        return Err(RouteError::Empty);
+    }
+
+    let mut pools = HashSet::new();
+    for (i, hop) in hops.iter().enumerate() {
+        let encode_error = |source| RouteError::Encode { hop: i, source };
+        let lp_token = hop.pool_state.lp_token();
+        if bytes_to_address(&hop.token_in).map_err(encode_error)? == lp_token
+            || bytes_to_address(&hop.token_out).map_err(encode_error)? == lp_token
+        {
+            return Err(RouteError::LpToken(i));
+        }
+        if i > 0 && hop.token_in != hops[i - 1].token_out {
+            return Err(RouteError::Disconnected(i));
+        }
+        let pool = hop.pool_state.address();
+        if !pools.insert(pool) {
+            return Err(RouteError::DuplicatePool(pool));
+        }
+    }
+    Ok(())
+}
+
+/// Amount of `token` the hop trades, which is passed on to the adjacent hop.
+fn hop_amount(
+    hop: usize,
+    template: &TemplateOrder,
+    token: &Bytes,
+) -> Result<BigUint, RouteError> {
+    let encode_error = |source| RouteError::Encode { hop, source };
+    let token = bytes_to_address(token).map_err(encode_error)?;
+    let amount = traded_amount(template, token).map_err(encode_error)?;
+    Ok(u256_to_biguint(amount))
+}
+
+fn u256_to_biguint(value: U256) -> BigUint {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    BigUint::from_bytes_be(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::testing::{address_bytes, helper_response, pool_state},
+        contracts::BCowHelper,
+        ethcontract::{Address, H160},
+        ethcontract_mock::Mock,
+    };
+
+    const POOL_1: Address = H160([0x11; 20]);
+    const POOL_2: Address = H160([0x22; 20]);
+    const COW: Address = H160([0xaa; 20]);
+    const WETH: Address = H160([0xbb; 20]);
+    const WSTETH: Address = H160([0xcc; 20]);
+
+    fn hop(token_in: Address, token_out: Address, pool: Address, tokens: [Address; 2]) -> Hop {
+        Hop {
+            token_in: address_bytes(token_in),
+            token_out: address_bytes(token_out),
+            pool_state: Arc::new(pool_state(pool, tokens[0], tokens[1])),
+        }
+    }
+
+    fn route() -> Vec<Hop> {
+        vec![
+            hop(COW, WETH, POOL_1, [COW, WETH]),
+            hop(WETH, WSTETH, POOL_2, [WETH, WSTETH]),
+        ]
+    }
+
+    fn mock_helper(mock: &Mock) -> ethcontract_mock::Contract {
+        let helper = mock.deploy(BCowHelper::raw_contract().interface.abi.clone());
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .predicate_fn(|(pool,)| *pool == POOL_1)
+            .returns(vec![COW, WETH]);
+        helper
+            .expect_call(BCowHelper::signatures().tokens())
+            .predicate_fn(|(pool,)| *pool == POOL_2)
+            .returns(vec![WETH, WSTETH]);
+        helper
+    }
+
+    #[tokio::test]
+    async fn chains_sell_amounts_through_hops() {
+        let mock = Mock::new(1);
+        let helper = mock_helper(&mock);
+        // Every pool buys what the route sells into it.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .predicate_fn(|(pool, token, amount)| {
+                *pool == POOL_1 && *token == COW && *amount == 1_000.into()
+            })
+            .returns(helper_response(POOL_1, WETH, COW, 990.into(), 1_000.into()));
+        helper
+            .expect_call(BCowHelper::signatures().order_from_buy_amount())
+            .once()
+            .predicate_fn(|(pool, token, amount)| {
+                *pool == POOL_2 && *token == WETH && *amount == 990.into()
+            })
+            .returns(helper_response(POOL_2, WSTETH, WETH, 980.into(), 990.into()));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let route = encoder
+            .encode_route(OrderKind::Sell, BigUint::from(1_000_u32), &route())
+            .await
+            .unwrap();
+
+        assert_eq!(route.templates.len(), 2);
+        assert_eq!(route.templates[0].order.receiver, Some(POOL_1));
+        assert_eq!(route.templates[1].order.receiver, Some(POOL_2));
+        assert_eq!(route.templates[1].order.sell_amount, 980.into());
+        // Commitments of both pools, in route order.
+        let targets: Vec<_> = route.pre_interactions.iter().map(|i| i.target).collect();
+        assert_eq!(targets, vec![POOL_1, POOL_2]);
+        assert!(route.post_interactions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn chains_buy_amounts_backwards() {
+        let mock = Mock::new(1);
+        let helper = mock_helper(&mock);
+        // Every pool sells what the route buys from it.
+        helper
+            .expect_call(BCowHelper::signatures().order_from_sell_amount())
+            .once()
+            .predicate_fn(|(pool, token, amount)| {
+                *pool == POOL_2 && *token == WSTETH && *amount == 980.into()
+            })
+            .returns(helper_response(POOL_2, WSTETH, WETH, 980.into(), 990.into()));
+        helper
+            .expect_call(BCowHelper::signatures().order_from_sell_amount())
+            .once()
+            .predicate_fn(|(pool, token, amount)| {
+                *pool == POOL_1 && *token == WETH && *amount == 990.into()
+            })
+            .returns(helper_response(POOL_1, WETH, COW, 990.into(), 1_000.into()));
+
+        let encoder = CowAmmEncoder::with_helper(&mock.web3(), 1, helper.address());
+        let route = encoder
+            .encode_route(OrderKind::Buy, BigUint::from(980_u32), &route())
+            .await
+            .unwrap();
+
+        assert_eq!(route.templates[0].order.buy_token, COW);
+        assert_eq!(route.templates[0].order.buy_amount, 1_000.into());
+        assert_eq!(route.templates[1].order.sell_token, WSTETH);
+        let targets: Vec<_> = route.pre_interactions.iter().map(|i| i.target).collect();
+        assert_eq!(targets, vec![POOL_1, POOL_2]);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_routes() {
+        let encoder = CowAmmEncoder::with_helper(&contracts::web3::dummy(), 1, H160([0x42; 20]));
+        let amount = BigUint::from(1_000_u32);
+
+        let result = encoder.encode_route(OrderKind::Sell, amount.clone(), &[]).await;
+        assert!(matches!(result, Err(RouteError::Empty)));
+
+        let hops = [
+            hop(COW, WETH, POOL_1, [COW, WETH]),
+            hop(COW, WSTETH, POOL_2, [COW, WSTETH]),
+        ];
+        let result = encoder.encode_route(OrderKind::Sell, amount.clone(), &hops).await;
+        assert!(matches!(result, Err(RouteError::Disconnected(1))));
+
+        let hops = [
+            hop(COW, WETH, POOL_1, [COW, WETH]),
+            hop(WETH, COW, POOL_1, [COW, WETH]),
+        ];
+        let result = encoder.encode_route(OrderKind::Sell, amount.clone(), &hops).await;
+        assert!(matches!(result, Err(RouteError::DuplicatePool(POOL_1))));
+
+        // The LP token of the test pools is the pool itself.
+        let hops = [hop(COW, POOL_1, POOL_1, [COW, WETH])];
+        let result = encoder.encode_route(OrderKind::Sell, amount, &hops).await;
+        assert!(matches!(result, Err(RouteError::LpToken(0))));
+    }
+}
@@ -0,0 +1,372 @@
+//! Encoding of `GPv2Settlement.settle` calls.
+//!
+//! Template orders of CoW AMMs are settled like any other order: the pool is
+//! the owner of an EIP-1271 signed trade and the pre- and post-interactions of
+//! the template run before and after the trades. A [`Settlement`] collects
+//! templates, user trades and interactions and encodes them into the
+//! arguments of `settle`.
+
+use {
+    crate::errors::SettlementError,
+    contracts::{GPv2Settlement, dummy_contract},
+    cow_amm::helper::TemplateOrder,
+    ethcontract::{Address, U256, tokens::Bytes},
+    model::{
+        interaction::InteractionData,
+        order::{BuyTokenDestination, OrderData, OrderKind, SellTokenSource},
+        signature::Signature,
+    },
+    std::collections::HashMap,
+};
+
+/// `GPv2Trade.Data` as expected by the `settle` binding.
+pub type EncodedTrade = (
+    U256,
+    U256,
+    Address,
+    U256,
+    U256,
+    u32,
+    Bytes<[u8; 32]>,
+    U256,
+    U256,
+    U256,
+    Bytes<Vec<u8>>,
+);
+
+/// `GPv2Interaction.Data` as expected by the `settle` binding.
+pub type EncodedInteraction = (Address, U256, Bytes<Vec<u8>>);
+
+/// Point of the settlement at which an interaction is executed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InteractionPhase {
+    /// Before any funds are transferred into the settlement contract.
+    Pre,
+    /// After the sell amounts were transferred in and before the buy amounts
+    /// are transferred out.
+    Intra,
+    /// After the buy amounts were transferred out.
+    Post,
+}
+
+/// Order that is executed by a settlement.
+#[derive(Clone, Debug)]
+pub struct Trade {
+    pub order: OrderData,
+    /// Account that signed the order and whose funds are traded.
+    pub owner: Address,
+    pub signature: Signature,
+    /// Amount of the sell token for sell orders and of the buy token for buy
+    /// orders that is executed.
+    pub executed_amount: U256,
+}
+
+impl Trade {
+    /// Trade that fully executes the order of a template for `pool`.
+    pub fn template(pool: Address, template: &TemplateOrder) -> Self {
+        let order = template.order.clone();
+        let executed_amount = match order.kind {
+            OrderKind::Sell => order.sell_amount,
+            OrderKind::Buy => order.buy_amount,
+        };
+        Self {
+            order,
+            owner: pool,
+            signature: template.signature.clone(),
+            executed_amount,
+        }
+    }
+}
+
+/// Trades and interactions of a single `settle` call.
+#[derive(Clone, Debug, Default)]
+pub struct Settlement {
+    clearing_prices: HashMap<Address, U256>,
+    trades: Vec<Trade>,
+    pre_interactions: Vec<InteractionData>,
+    intra_interactions: Vec<InteractionData>,
+    post_interactions: Vec<InteractionData>,
+}
+
+/// Arguments of a `settle` call.
+#[derive(Clone, Debug, Default)]
+pub struct EncodedSettlement {
+    pub tokens: Vec<Address>,
+    pub clearing_prices: Vec<U256>,
+    pub trades: Vec<EncodedTrade>,
+    pub interactions: [Vec<EncodedInteraction>; 3],
+}
+
+impl Settlement {
+    /// Creates an empty settlement that executes its trades at the uniform
+    /// `clearing_prices`.
+    pub fn new(clearing_prices: HashMap<Address, U256>) -> Self {
+        Self {
+            clearing_prices,
+            ..Default::default()
+        }
+    }
+
+    /// Adds the order of a CoW AMM template, executed in full, together with
+    /// its pre- and post-interactions.
+    pub fn add_template(&mut self, pool: Address, template: &TemplateOrder) {
+        self.trades.push(Trade::template(pool, template));
+        self.pre_interactions
+            .extend(template.pre_interactions.iter().cloned());
+        self.post_interactions
+            .extend(template.post_interactions.iter().cloned());
+    }
+
+    /// Adds a trade, e.g. of a user order that the template orders are
+    /// matched with.
+    pub fn add_trade(&mut self, trade: Trade) {
+        self.trades.push(trade);
+    }
+
+    /// Adds an interaction that is executed at `phase`, after the ones that
+    /// were added before.
+    pub fn add_interaction(&mut self, phase: InteractionPhase, interaction: InteractionData) {
+        match phase {
+            InteractionPhase::Pre => &mut self.pre_interactions,
+            InteractionPhase::Intra => &mut self.intra_interactions,
+            InteractionPhase::Post => &mut self.post_interactions,
+        }
+        .push(interaction);
+    }
+
+    /// Encodes the settlement into the arguments of `settle`.
+    ///
+    /// Tokens are listed in the order the trades first use them and every
+    /// one of them needs a clearing price.
+    pub fn encode(&self) -> Result<EncodedSettlement, SettlementError> {
+        let mut tokens = Vec::new();
+        let mut token_index = |token: Address| -> Result<usize, SettlementError> {
+            if let Some(index) = tokens.iter().position(|&existing| existing == token) {
+                return Ok(index);
+            }
+            if !self.clearing_prices.contains_key(&token) {
+                return Err(SettlementError::MissingPrice(token));
+            }
+            tokens.push(token);
+            Ok(tokens.len() - 1)
+        };
+
+        let mut trades = Vec::with_capacity(self.trades.len());
+        for trade in &self.trades {
+            let sell_token_index = token_index(trade.order.sell_token)?;
+            let buy_token_index = token_index(trade.order.buy_token)?;
+            trades.push(encode_trade(trade, sell_token_index, buy_token_index));
+        }
+        let clearing_prices = tokens
+            .iter()
+            .map(|token| self.clearing_prices[token])
+            .collect();
+
+        Ok(EncodedSettlement {
+            tokens,
+            clearing_prices,
+            trades,
+            interactions: [
+                encode_interactions(&self.pre_interactions),
+                encode_interactions(&self.intra_interactions),
+                encode_interactions(&self.post_interactions),
+            ],
+        })
+    }
+}
+
+impl EncodedSettlement {
+    /// Calldata of the `settle` call.
+    pub fn calldata(&self) -> Vec<u8> {
+        dummy_contract!(GPv2Settlement, Address::zero())
+            .settle(
+                self.tokens.clone(),
+                self.clearing_prices.clone(),
+                self.trades.clone(),
+                self.interactions.clone(),
+            )
+            .tx
+            .data
+            .expect("no calldata")
+            .0
+    }
+}
+
+fn encode_trade(trade: &Trade, sell_token_index: usize, buy_token_index: usize) -> EncodedTrade {
+    let order = &trade.order;
+    (
+        sell_token_index.into(),
+        buy_token_index.into(),
+        order.receiver.unwrap_or_default(),
+        order.sell_amount,
+        order.buy_amount,
+        order.valid_to,
+        Bytes(order.app_data.0),
+        order.fee_amount,
+        trade_flags(order, &trade.signature),
+        trade.executed_amount,
+        Bytes(trade.signature.encode_for_settlement(trade.owner)),
+    )
+}
+
+/// `GPv2Trade` flags of an order, which encode its kind, whether it is
+/// partially fillable, its token balances and its signing scheme.
+pub fn trade_flags(order: &OrderData, signature: &Signature) -> U256 {
+    let kind = match order.kind {
+        OrderKind::Sell => 0,
+        OrderKind::Buy => 1,
+    };
+    let partially_fillable = if order.partially_fillable { 1 << 1 } else { 0 };
+    let sell_token_balance = match order.sell_token_balance {
+        SellTokenSource::Erc20 => 0,
+        SellTokenSource::External => 2 << 2,
+        SellTokenSource::Internal => 3 << 2,
+    };
+    let buy_token_balance = match order.buy_token_balance {
+        BuyTokenDestination::Erc20 => 0,
+        BuyTokenDestination::Internal => 1 << 4,
+    };
+    let signing_scheme = match signature {
+        Signature::Eip712(_) => 0,
+        Signature::EthSign(_) => 1 << 5,
+        Signature::Eip1271(_) => 2 << 5,
+        Signature::PreSign => 3 << 5,
+    };
+    U256::from(kind | partially_fillable | sell_token_balance | buy_token_balance | signing_scheme)
+}
+
+pub fn encode_interactions(interactions: &[InteractionData]) -> Vec<EncodedInteraction> {
+    interactions
+        .iter()
+        .map(|interaction| {
+            (
+                interaction.target,
+                interaction.value,
+                Bytes(interaction.call_data.clone()),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, ethcontract::H160, maplit::hashmap, model::signature::EcdsaSignature};
+
+    const POOL: Address = H160([0x11; 20]);
+    const USER: Address = H160([0x22; 20]);
+    const TOKEN_A: Address = H160([0xaa; 20]);
+    const TOKEN_B: Address = H160([0xbb; 20]);
+    const TOKEN_C: Address = H160([0xcc; 20]);
+
+    fn interaction(byte: u8) -> InteractionData {
+        InteractionData {
+            target: POOL,
+            value: U256::zero(),
+            call_data: vec![byte; 4],
+        }
+    }
+
+    fn template() -> TemplateOrder {
+        TemplateOrder {
+            order: OrderData {
+                sell_token: TOKEN_A,
+                buy_token: TOKEN_B,
+                receiver: Some(H160::zero()),
+                sell_amount: 100.into(),
+                buy_amount: 90.into(),
+                valid_to: 1_700_000_300,
+                kind: OrderKind::Sell,
+                ..Default::default()
+            },
+            signature: Signature::Eip1271(vec![0xee; 8]),
+            pre_interactions: vec![interaction(1)],
+            post_interactions: vec![interaction(3)],
+            slippage_bps: None,
+            gas_estimate: 0,
+        }
+    }
+
+    fn user_trade() -> Trade {
+        Trade {
+            order: OrderData {
+                sell_token: TOKEN_B,
+                buy_token: TOKEN_C,
+                sell_amount: 50.into(),
+                buy_amount: 40.into(),
+                kind: OrderKind::Buy,
+                partially_fillable: true,
+                sell_token_balance: SellTokenSource::External,
+                buy_token_balance: BuyTokenDestination::Internal,
+                ..Default::default()
+            },
+            owner: USER,
+            signature: Signature::Eip712(EcdsaSignature::default()),
+            executed_amount: 20.into(),
+        }
+    }
+
+    #[test]
+    fn encodes_templates_and_trades() {
+        let mut settlement = Settlement::new(hashmap! {
+            TOKEN_A => 9.into(),
+            TOKEN_B => 10.into(),
+            TOKEN_C => 11.into(),
+        });
+        settlement.add_template(POOL, &template());
+        settlement.add_trade(user_trade());
+        settlement.add_interaction(InteractionPhase::Intra, interaction(2));
+        let encoded = settlement.encode().unwrap();
+
+        assert_eq!(encoded.tokens, vec![TOKEN_A, TOKEN_B, TOKEN_C]);
+        assert_eq!(
+            encoded.clearing_prices,
+            vec![9.into(), 10.into(), 11.into()]
+        );
+
+        let pool_trade = &encoded.trades[0];
+        assert_eq!((pool_trade.0, pool_trade.1), (0.into(), 1.into()));
+        // Sell order, ERC20 balances, EIP-1271 signature.
+        assert_eq!(pool_trade.8, 0x40.into());
+        assert_eq!(pool_trade.9, 100.into());
+        assert_eq!(pool_trade.10.0, [POOL.as_bytes(), &[0xee; 8]].concat());
+
+        let user_trade = &encoded.trades[1];
+        assert_eq!((user_trade.0, user_trade.1), (1.into(), 2.into()));
+        assert_eq!(user_trade.8, 0b1_1011.into());
+        assert_eq!(user_trade.9, 20.into());
+        assert_eq!(user_trade.10.0.len(), 65);
+
+        let calls = encoded.interactions.clone().map(|phase| {
+            phase
+                .into_iter()
+                .map(|(_, _, data)| data.0)
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(
+            calls,
+            [vec![vec![1; 4]], vec![vec![2; 4]], vec![vec![3; 4]]]
+        );
+
+        assert_eq!(encoded.calldata()[..4], hex_literal::hex!("13d79a0b"));
+    }
+
+    #[test]
+    fn requires_prices_for_traded_tokens() {
+        let mut settlement = Settlement::new(hashmap! { TOKEN_A => 1.into() });
+        settlement.add_template(POOL, &template());
+        assert!(matches!(
+            settlement.encode(),
+            Err(SettlementError::MissingPrice(token)) if token == TOKEN_B
+        ));
+    }
+
+    #[test]
+    fn encodes_signing_schemes() {
+        let order = OrderData::default();
+        let flags = |signature| trade_flags(&order, &signature);
+        assert_eq!(flags(Signature::Eip712(Default::default())), 0.into());
+        assert_eq!(flags(Signature::EthSign(Default::default())), 0x20.into());
+        assert_eq!(flags(Signature::Eip1271(Vec::new())), 0x40.into());
+        assert_eq!(flags(Signature::PreSign), 0x60.into());
+    }
+}
@@ -0,0 +1,664 @@
+//! Local simulation of settlements that execute CoW AMM template orders.
+//!
+//! Settlements are executed with revm against a snapshot of the accounts
+//! they touch, so a template can be checked without a node and before it is
+//! submitted. Snapshots of real pools can be recorded from a node with
+//! [`record_snapshot`].
+
+use {
+    crate::{encode_cowamm::ethcontract_to_alloy, errors::SimulationError, settlement::Settlement},
+    contracts::{ERC20, GPv2Settlement, cow_amm_revert::CowAmmRevert, dummy_contract},
+    cow_amm::helper::TemplateOrder,
+    ethcontract::{
+        Address, H256, U256,
+        common::abi::{self, ParamType, Token},
+        dyns::DynWeb3,
+        web3::{
+            Transport,
+            signing::keccak256,
+            types::{BlockId, BlockNumber, Bytes, CallRequest},
+        },
+    },
+    model::signature::Signature,
+    revm::{
+        Context, ExecuteCommitEvm, MainBuilder, MainContext,
+        bytecode::Bytecode,
+        context::TxEnv,
+        context_interface::result::{ExecutionResult, Output},
+        database::{CacheDB, EmptyDB},
+        primitives::{Address as AlloyAddress, TxKind},
+        state::AccountInfo,
+    },
+    serde::{Deserialize, Serialize},
+    std::collections::HashMap,
+};
+
+/// Gas limit of every simulated transaction.
+const GAS_LIMIT: u64 = 30_000_000;
+
+/// Selector of `Error(string)`, which `require` reverts with.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Block the snapshot was taken at.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BlockSnapshot {
+    pub number: u64,
+    pub timestamp: u64,
+}
+
+/// State of a single account, in the format of geth's `prestateTracer`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AccountSnapshot {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code: Bytes,
+    pub storage: HashMap<H256, H256>,
+}
+
+/// Accounts a settlement touches, e.g. the settlement contract, its vault
+/// relayer and authenticator, the pools and their tokens.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StateSnapshot {
+    pub block: BlockSnapshot,
+    pub accounts: HashMap<Address, AccountSnapshot>,
+}
+
+/// Token balance of an account before and after the simulated settlement.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TokenBalance {
+    pub token: Address,
+    pub owner: Address,
+    pub before: U256,
+    pub after: U256,
+}
+
+/// Outcome of a successfully simulated settlement.
+#[derive(Clone, Debug)]
+pub struct SimulatedSettlement {
+    /// Gas used by the `settle` transaction.
+    pub gas_used: u64,
+    /// Balances of both traded tokens for the pool and the settlement
+    /// contract.
+    pub balances: Vec<TokenBalance>,
+}
+
+/// Executes settlements with template orders against a [`StateSnapshot`].
+#[derive(Clone, Debug)]
+pub struct SettlementSimulator {
+    snapshot: StateSnapshot,
+    chain_id: u64,
+    settlement: Address,
+    solver: Address,
+}
+
+impl SettlementSimulator {
+    /// Creates a simulator that settles through the `GPv2Settlement` at
+    /// `settlement` as `solver`, which has to be allowed by the
+    /// authenticator in the snapshot.
+    pub fn new(
+        snapshot: StateSnapshot,
+        chain_id: u64,
+        settlement: Address,
+        solver: Address,
+    ) -> Self {
+        Self {
+            snapshot,
+            chain_id,
+            settlement,
+            solver,
+        }
+    }
+
+    /// Simulates a settlement that executes the pre-interactions of
+    /// `template`, trades its order of `pool` in full at the limit price and
+    /// executes its post-interactions.
+    ///
+    /// The settlement pays the pool from its own balance of the buy token, so
+    /// the snapshot needs to contain enough of it.
+    pub fn simulate(
+        &self,
+        pool: Address,
+        template: &TemplateOrder,
+    ) -> Result<SimulatedSettlement, SimulationError> {
+        if !matches!(template.signature, Signature::Eip1271(_)) {
+            return Err(SimulationError::UnsupportedSignature);
+        }
+        let order = &template.order;
+        let tokens = [order.sell_token, order.buy_token];
+        for account in [self.settlement, pool].iter().chain(&tokens) {
+            self.ensure_code(*account)?;
+        }
+
+        let mut execution = Execution {
+            db: self.database(),
+            nonce: self
+                .snapshot
+                .accounts
+                .get(&self.solver)
+                .map_or(0, |account| account.nonce),
+            simulator: self,
+        };
+        let owners = [pool, self.settlement];
+        let before = execution.balances(&tokens, &owners)?;
+        let settle = Self::encode_settle(pool, template);
+        let gas_used = match execution.transact(self.settlement, settle)? {
+            ExecutionResult::Success { gas_used, .. } => gas_used,
+            ExecutionResult::Revert { output, .. } => {
+                return Err(SimulationError::Reverted(revert_reason(&output)));
+            }
+            ExecutionResult::Halt { reason, .. } => {
+                return Err(SimulationError::Halted(format!("{reason:?}")));
+            }
+        };
+        let after = execution.balances(&tokens, &owners)?;
+
+        let balances = before
+            .into_iter()
+            .zip(after)
+            .map(|((token, owner, before), (_, _, after))| TokenBalance {
+                token,
+                owner,
+                before,
+                after,
+            })
+            .collect();
+        Ok(SimulatedSettlement { gas_used, balances })
+    }
+
+    fn ensure_code(&self, address: Address) -> Result<(), SimulationError> {
+        match self.snapshot.accounts.get(&address) {
+            Some(account) if !account.code.0.is_empty() => Ok(()),
+            _ => Err(SimulationError::MissingCode(address)),
+        }
+    }
+
+    fn database(&self) -> CacheDB<EmptyDB> {
+        let mut db = CacheDB::<EmptyDB>::default();
+        for (address, account) in &self.snapshot.accounts {
+            let address = alloy_address(*address);
+            let code = Bytecode::new_raw(account.code.0.clone().into());
+            db.insert_account_info(
+                address,
+                AccountInfo::new(
+                    ethcontract_to_alloy(account.balance),
+                    account.nonce,
+                    code.hash_slow(),
+                    code,
+                ),
+            );
+            for (slot, value) in &account.storage {
+                db.insert_account_storage(
+                    address,
+                    ethcontract_to_alloy(U256::from_big_endian(slot.as_bytes())),
+                    ethcontract_to_alloy(U256::from_big_endian(value.as_bytes())),
+                )
+                .expect("empty database never fails");
+            }
+        }
+        db
+    }
+
+    /// `settle` calldata that trades the template order at its limit price.
+    fn encode_settle(pool: Address, template: &TemplateOrder) -> Vec<u8> {
+        let order = &template.order;
+        let mut settlement = Settlement::new(HashMap::from([
+            (order.sell_token, order.buy_amount),
+            (order.buy_token, order.sell_amount),
+        ]));
+        settlement.add_template(pool, template);
+        settlement
+            .encode()
+            .expect("both traded tokens have a price")
+            .calldata()
+    }
+}
+
+/// Records the accounts that settling `template` of `pool` touches at
+/// `block`, using geth's `prestateTracer` on the node behind `web3`.
+///
+/// `solver` is allowed by the settlement's authenticator for the traced call
+/// and in the returned snapshot, so it can be simulated with any solver. The
+/// snapshot can be serialized and stored as a fixture for offline tests.
+pub async fn record_snapshot(
+    web3: &DynWeb3,
+    settlement: Address,
+    solver: Address,
+    pool: Address,
+    template: &TemplateOrder,
+    block: u64,
+) -> Result<StateSnapshot, SimulationError> {
+    let node = |err: ethcontract::web3::Error| SimulationError::Node(err.to_string());
+    let block_number = BlockNumber::Number(block.into());
+    let authenticator = GPv2Settlement::at(web3, settlement)
+        .authenticator()
+        .block(BlockId::Number(block_number))
+        .call()
+        .await
+        .map_err(|err| SimulationError::Node(err.to_string()))?;
+
+    let (solver_slot, allowed) = (allow_list_slot(solver), H256::from_low_u64_be(1));
+    let call = CallRequest {
+        from: Some(solver),
+        to: Some(settlement),
+        gas: Some(GAS_LIMIT.into()),
+        data: Some(SettlementSimulator::encode_settle(pool, template).into()),
+        ..Default::default()
+    };
+    let config = serde_json::json!({
+        "tracer": "prestateTracer",
+        "stateOverrides": {
+            format!("{authenticator:?}"): {
+                "stateDiff": { format!("{solver_slot:?}"): allowed },
+            },
+        },
+    });
+    let params = vec![
+        serde_json::to_value(call).expect("call request serializes"),
+        serde_json::to_value(block_number).expect("block number serializes"),
+        config,
+    ];
+    let prestate = web3
+        .transport()
+        .execute("debug_traceCall", params)
+        .await
+        .map_err(node)?;
+    let mut accounts: HashMap<Address, AccountSnapshot> = serde_json::from_value(prestate)
+        .map_err(|err| SimulationError::Node(format!("invalid prestate: {err}")))?;
+    accounts
+        .entry(authenticator)
+        .or_default()
+        .storage
+        .insert(solver_slot, allowed);
+
+    let header = web3
+        .eth()
+        .block(BlockId::Number(block_number))
+        .await
+        .map_err(node)?
+        .ok_or_else(|| SimulationError::Node(format!("missing block {block}")))?;
+    Ok(StateSnapshot {
+        block: BlockSnapshot {
+            number: block,
+            timestamp: header.timestamp.as_u64(),
+        },
+        accounts,
+    })
+}
+
+/// Storage slot of `solver` in the `solvers` mapping of
+/// `GPv2AllowListAuthentication`, which is the second slot of the contract.
+fn allow_list_slot(solver: Address) -> H256 {
+    H256(keccak256(&abi::encode(&[
+        Token::Address(solver),
+        Token::Uint(1.into()),
+    ])))
+}
+
+/// revm instance with the snapshot state that keeps track of the solver's
+/// nonce across transactions.
+struct Execution<'a> {
+    db: CacheDB<EmptyDB>,
+    nonce: u64,
+    simulator: &'a SettlementSimulator,
+}
+
+impl Execution<'_> {
+    fn transact(&mut self, to: Address, data: Vec<u8>) -> Result<ExecutionResult, SimulationError> {
+        let simulator = self.simulator;
+        let block = &simulator.snapshot.block;
+        let mut evm = Context::mainnet()
+            .with_db(&mut self.db)
+            .modify_block_chained(|env| {
+                env.number = block.number.into();
+                env.timestamp = block.timestamp.into();
+            })
+            .modify_cfg_chained(|cfg| cfg.chain_id = simulator.chain_id)
+            .build_mainnet();
+        let result = evm
+            .transact_commit(TxEnv {
+                caller: alloy_address(simulator.solver),
+                kind: TxKind::Call(alloy_address(to)),
+                data: data.into(),
+                gas_limit: GAS_LIMIT,
+                nonce: self.nonce,
+                chain_id: Some(simulator.chain_id),
+                ..Default::default()
+            })
+            .map_err(|err| SimulationError::Evm(err.to_string()))?;
+        self.nonce += 1;
+        Ok(result)
+    }
+
+    /// Balances of every token for every owner.
+    fn balances(
+        &mut self,
+        tokens: &[Address],
+        owners: &[Address],
+    ) -> Result<Vec<(Address, Address, U256)>, SimulationError> {
+        let mut balances = Vec::new();
+        for token in tokens {
+            for owner in owners {
+                let call = dummy_contract!(ERC20, *token).balance_of(*owner);
+                let data = call.tx.data.expect("no calldata").0;
+                let balance = match self.transact(*token, data)? {
+                    ExecutionResult::Success {
+                        output: Output::Call(output),
+                        ..
+                    } if output.len() == 32 => U256::from_big_endian(&output),
+                    _ => return Err(SimulationError::InvalidToken(*token)),
+                };
+                balances.push((*token, *owner, balance));
+            }
+        }
+        Ok(balances)
+    }
+}
+
+/// Human readable reason of a revert: the name of a known custom error, the
+/// message of a `require` or the raw revert data.
+fn revert_reason(output: &[u8]) -> String {
+    if let Some(revert) = CowAmmRevert::decode(output) {
+        return revert.name().to_owned();
+    }
+    if let Some(data) = output.strip_prefix(&ERROR_SELECTOR) {
+        if let Ok(mut tokens) = abi::decode(&[ParamType::String], data) {
+            if let Some(Token::String(message)) = tokens.pop() {
+                return message;
+            }
+        }
+    }
+    format!("0x{}", hex::encode(output))
+}
+
+fn alloy_address(address: Address) -> AlloyAddress {
+    AlloyAddress::from(address.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            encode_cowamm::interaction_data,
+            weth::{WethDepositInteraction, WethWithdrawInteraction},
+        },
+        contracts::WETH9,
+        cow_amm::{bnum::BONE, local_helper::PoolSnapshot},
+        ethcontract::H160,
+        hex_literal::hex,
+        model::DomainSeparator,
+    };
+
+    // The snapshot deploys `GPv2Settlement`, its authenticator, two
+    // `ERC20Mintable` tokens and `WETH9` from the vendored artifacts. The pool
+    // holds 1000 of each token and the settlement contract 100 of each
+    // `ERC20Mintable` and 100 ETH, but no WETH. `BCowPool` has no
+    // vendored bytecode, so the pool is a stand-in that accepts signatures
+    // for exactly the order hash it was last committed to. A real pool is
+    // covered by `settles_recorded_bcow_pool_state`, which replays the state
+    // `record_bcow_pool_state` recorded from a node.
+    const SNAPSHOT: &str = include_str!("../testdata/settlement_state.json");
+    const SETTLEMENT: Address = H160(hex!("aa5b7967f8d0ab7df65848a97d880e67aade56c6"));
+    const SOLVER: Address = H160([0x50; 20]);
+    const POOL: Address = H160([0x11; 20]);
+    const TOKEN_A: Address = H160(hex!("67d7895412f0131a854d76b3418b5ef4c37da55d"));
+    const TOKEN_B: Address = H160(hex!("19a203c7a09a8f848a45c1e1820c512ebf5d70f6"));
+    const WETH: Address = H160(hex!("da33b3c2f027889ce6a96209290399f76001e0b8"));
+    const TIMESTAMP: u64 = 1_700_000_000;
+
+    fn simulator() -> SettlementSimulator {
+        let snapshot = serde_json::from_str(SNAPSHOT).unwrap();
+        SettlementSimulator::new(snapshot, 1, SETTLEMENT, SOLVER)
+    }
+
+    /// Asserts that the template's gas estimate is within 50% of the gas the
+    /// simulated settlement used.
+    fn assert_gas_estimate(template: &TemplateOrder, settlement: &SimulatedSettlement) {
+        let (estimate, used) = (template.gas_estimate, settlement.gas_used);
+        assert!(
+            estimate.abs_diff(used) * 2 <= used,
+            "estimated {estimate} gas but the settlement used {used}"
+        );
+    }
+
+    fn template(timestamp: u64) -> TemplateOrder {
+        let pool = PoolSnapshot {
+            address: POOL,
+            tokens: [TOKEN_A, TOKEN_B],
+            balances: [BONE * 1000, BONE * 1000],
+            weights: [BONE, BONE],
+            domain_separator: DomainSeparator::new(1, SETTLEMENT),
+        };
+        pool.order_from_sell_amount(TOKEN_A, BONE * 10, timestamp)
+            .unwrap()
+    }
+
+    #[test]
+    fn settles_template_order() {
+        let template = template(TIMESTAMP);
+        let bought = template.order.buy_amount;
+        let settlement = simulator().simulate(POOL, &template).unwrap();
+
+        assert!((100_000..200_000).contains(&settlement.gas_used));
+        let balance = |token, owner| {
+            settlement
+                .balances
+                .iter()
+                .find(|balance| balance.token == token && balance.owner == owner)
+                .cloned()
+                .unwrap()
+        };
+        let sold = balance(TOKEN_A, POOL);
+        assert_eq!(sold.before - sold.after, BONE * 10);
+        let received = balance(TOKEN_B, POOL);
+        assert_eq!(received.after - received.before, bought);
+        let paid = balance(TOKEN_B, SETTLEMENT);
+        assert_eq!(paid.before - paid.after, bought);
+    }
+
+    #[test]
+    fn estimates_simulated_gas() {
+        let template = template(TIMESTAMP);
+        let settlement = simulator().simulate(POOL, &template).unwrap();
+        assert_eq!(template.gas_estimate, template.estimate_gas());
+        assert_gas_estimate(&template, &settlement);
+    }
+
+    /// Template of a pool that trades `TOKEN_A` for `WETH`, which the
+    /// settlement wraps from its ETH when it pays WETH and unwraps when it
+    /// receives WETH, like `CowAmmEncoder` does for orders of native ETH.
+    fn weth_template(wrap: bool) -> TemplateOrder {
+        let (sell_token, buy_token) = if wrap {
+            (TOKEN_A, WETH)
+        } else {
+            (WETH, TOKEN_A)
+        };
+        let pool = PoolSnapshot {
+            address: POOL,
+            tokens: [sell_token, buy_token],
+            balances: [BONE * 1000, BONE * 1000],
+            weights: [BONE, BONE],
+            domain_separator: DomainSeparator::new(1, SETTLEMENT),
+        };
+        let mut template = pool
+            .order_from_sell_amount(sell_token, BONE * 10, TIMESTAMP)
+            .unwrap();
+        let weth = dummy_contract!(WETH9, WETH);
+        if wrap {
+            let deposit = WethDepositInteraction {
+                weth,
+                amount: template.order.buy_amount,
+            };
+            template
+                .pre_interactions
+                .insert(0, interaction_data(&deposit));
+        } else {
+            let withdraw = WethWithdrawInteraction {
+                weth,
+                amount: template.order.sell_amount,
+            };
+            template.post_interactions.push(interaction_data(&withdraw));
+        }
+        template.update_gas_estimate();
+        template
+    }
+
+    #[test]
+    fn estimates_weth_wrapping_gas() {
+        for wrap in [true, false] {
+            let template = weth_template(wrap);
+            let settlement = simulator().simulate(POOL, &template).unwrap();
+            assert_gas_estimate(&template, &settlement);
+        }
+    }
+
+    /// Fixture of a real pool, written by `record_bcow_pool_state`.
+    const RECORDED_POOL: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/bcow_pool_state.json");
+
+    /// State of a real `BCowPool` together with the parameters the helper
+    /// prices its orders with at the recorded block.
+    #[derive(Deserialize, Serialize)]
+    struct RecordedPool {
+        settlement: Address,
+        pool: Address,
+        tokens: [Address; 2],
+        balances: [U256; 2],
+        weights: [U256; 2],
+        state: StateSnapshot,
+    }
+
+    impl RecordedPool {
+        /// The helper's order of the pool selling 0.001 of its first token
+        /// at the recorded block.
+        fn template(&self) -> TemplateOrder {
+            let pool = PoolSnapshot {
+                address: self.pool,
+                tokens: self.tokens,
+                balances: self.balances,
+                weights: self.weights,
+                domain_separator: DomainSeparator::new(1, self.settlement),
+            };
+            pool.order_from_sell_amount(self.tokens[0], U256::exp10(15), self.state.block.timestamp)
+                .unwrap()
+        }
+    }
+
+    /// Records the state of the `BCowPool` at `COW_AMM` from the mainnet
+    /// archive node at `NODE_URL` into the fixture that
+    /// `settles_recorded_bcow_pool_state` replays. The settlement contract
+    /// has to hold enough of the pool's buy token, like it does for WETH.
+    #[tokio::test]
+    #[ignore]
+    async fn record_bcow_pool_state() {
+        let node = std::env::var("NODE_URL").unwrap();
+        let pool: Address = std::env::var("COW_AMM").unwrap().parse().unwrap();
+        let web3 = ethcontract::Web3::new(ethcontract::dyns::DynTransport::new(
+            ethcontract::web3::transports::Http::new(&node).unwrap(),
+        ));
+        let settlement = contracts::deployment(GPv2Settlement::raw_contract(), 1)
+            .unwrap()
+            .address;
+        let header = web3
+            .eth()
+            .block(BlockId::Number(BlockNumber::Latest))
+            .await
+            .unwrap()
+            .unwrap();
+        let block = header.number.unwrap().as_u64();
+        let at = BlockId::Number(BlockNumber::Number(block.into()));
+
+        let b_cow_pool = contracts::BCowPool::at(&web3, pool);
+        let tokens: [Address; 2] = b_cow_pool
+            .get_final_tokens()
+            .block(at)
+            .call()
+            .await
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let (mut balances, mut weights) = ([U256::zero(); 2], [U256::zero(); 2]);
+        for (i, token) in tokens.iter().enumerate() {
+            balances[i] = ERC20::at(&web3, *token)
+                .balance_of(pool)
+                .block(at)
+                .call()
+                .await
+                .unwrap();
+            weights[i] = b_cow_pool
+                .get_denormalized_weight(*token)
+                .block(at)
+                .call()
+                .await
+                .unwrap();
+        }
+        let mut recorded = RecordedPool {
+            settlement,
+            pool,
+            tokens,
+            balances,
+            weights,
+            state: StateSnapshot {
+                block: BlockSnapshot {
+                    number: block,
+                    timestamp: header.timestamp.as_u64(),
+                },
+                accounts: HashMap::new(),
+            },
+        };
+        let template = recorded.template();
+        recorded.state = record_snapshot(&web3, settlement, SOLVER, pool, &template, block)
+            .await
+            .unwrap();
+        assert!(!recorded.state.accounts[&pool].code.0.is_empty());
+        std::fs::write(
+            RECORDED_POOL,
+            serde_json::to_string_pretty(&recorded).unwrap(),
+        )
+        .unwrap();
+    }
+
+    /// Settles the helper's order of the recorded pool, which commits to the
+    /// order and validates its EIP-1271 signature with the real pool. This is
+    /// also what the gas constants in `cow_amm::gas` have to be checked
+    /// against.
+    #[test]
+    #[ignore = "needs testdata/bcow_pool_state.json from record_bcow_pool_state"]
+    fn settles_recorded_bcow_pool_state() {
+        let recorded: RecordedPool =
+            serde_json::from_str(&std::fs::read_to_string(RECORDED_POOL).unwrap()).unwrap();
+        let template = recorded.template();
+        let simulator =
+            SettlementSimulator::new(recorded.state.clone(), 1, recorded.settlement, SOLVER);
+        let settled = simulator.simulate(recorded.pool, &template).unwrap();
+        let bought = settled
+            .balances
+            .iter()
+            .find(|balance| {
+                balance.token == template.order.buy_token && balance.owner == recorded.pool
+            })
+            .unwrap();
+        assert_eq!(bought.after - bought.before, template.order.buy_amount);
+        assert_gas_estimate(&template, &settled);
+    }
+
+    #[test]
+    fn reports_failures() {
+        let simulator = simulator();
+
+        let expired = template(TIMESTAMP - 600);
+        let result = simulator.simulate(POOL, &expired);
+        assert!(
+            matches!(&result, Err(SimulationError::Reverted(reason)) if reason == "GPv2: order expired")
+        );
+
+        let mut presigned = template(TIMESTAMP);
+        presigned.signature = Signature::PreSign;
+        let result = simulator.simulate(POOL, &presigned);
+        assert!(matches!(result, Err(SimulationError::UnsupportedSignature)));
+
+        let result = simulator.simulate(H160([0x99; 20]), &template(TIMESTAMP));
+        assert!(matches!(result, Err(SimulationError::MissingCode(_))));
+    }
+}
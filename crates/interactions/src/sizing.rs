@@ -0,0 +1,339 @@
+//! Off-chain sizing of the swaps that accompany single-sided pool joins and
+//! exits.
+//!
+//! CoW AMM orders are settled without a swap fee, so the formulas below use
+//! the fee-less weighted math of the pool.
+
+use {
+    cow_amm::bnum::{self, BNumError, BONE},
+    ethcontract::U256,
+};
+
+/// Part of `amount_in` to swap into the other pool token before joining, so
+/// that the rest and the bought tokens are proportional to the pool balances
+/// after the swap.
+///
+/// Swapping `s` and joining with the rest leaves the pool with
+/// `balance_in + amount_in` of the input token, which only works out if
+/// `(1 + s / balance_in) ^ total_weight = (1 + amount_in / balance_in) ^ weight_out`,
+/// so `s = balance_in * ((1 + amount_in / balance_in) ^ (weight_out / total_weight) - 1)`.
+pub fn swap_amount_for_join(
+    amount_in: U256,
+    balance_in: U256,
+    weight_in: U256,
+    total_weight: U256,
+) -> Result<U256, BNumError> {
+    let base = bnum::badd(BONE, bnum::bdiv(amount_in, balance_in)?)?;
+    let weight_out = bnum::bsub(total_weight, weight_in)?;
+    let exponent = bnum::bdiv(weight_out, total_weight)?;
+    let growth = bnum::bsub(bnum::bpow(base, exponent)?, BONE)?;
+    // Rounding may not swap more than the input.
+    Ok(bnum::bmul(balance_in, growth)?.min(amount_in))
+}
+
+/// LP tokens to burn so that exiting the pool and swapping the other tokens
+/// back into it yields `amount_out` of a token with `balance` and `weight`.
+///
+/// Burning a share `q` of the supply and selling the other tokens into the
+/// remaining pool yields `balance * (1 - (1 - q) ^ (total_weight / weight))`,
+/// so `q = 1 - (1 - amount_out / balance) ^ (weight / total_weight)`.
+pub fn lp_in_for_exact_token_out(
+    amount_out: U256,
+    balance: U256,
+    weight: U256,
+    total_weight: U256,
+    lp_supply: U256,
+) -> Result<U256, BNumError> {
+    let ratio_out = bnum::bdiv(amount_out, balance)?;
+    let base = bnum::bsub(BONE, ratio_out)?;
+    let exponent = bnum::bdiv(weight, total_weight)?;
+    let share = bnum::bsub(BONE, bnum::bpow(base, exponent)?)?;
+    bnum::bmul(lp_supply, share)
+}
+
+/// Amount of a token to buy out of the pool before a join that mints
+/// `lp_amount_out`, so that the join pulls no more than the bought amount
+/// from the balance the purchase leaves behind.
+///
+/// The join pulls `bmul(ratio, balance - x)` for `ratio = bdiv(lp_amount_out,
+/// lp_supply)`, which is at most `x` for `x = ceil(balance * ratio / (1 + ratio))`.
+pub fn swap_out_for_join(
+    lp_amount_out: U256,
+    balance: U256,
+    lp_supply: U256,
+) -> Result<U256, BNumError> {
+    let ratio = bnum::bdiv(lp_amount_out, lp_supply)?;
+    let scaled = balance.checked_mul(ratio).ok_or(BNumError::MulOverflow)?;
+    let denominator = bnum::badd(BONE, ratio)?;
+    Ok((scaled + denominator - 1) / denominator)
+}
+
+/// Amounts of every token `joinPool` pulls for minting, or `exitPool`
+/// returns for burning, `lp_amount` of a pool with `balances`.
+pub fn proportional_amounts(
+    lp_amount: U256,
+    lp_supply: U256,
+    balances: &[U256],
+) -> Result<Vec<U256>, BNumError> {
+    let ratio = bnum::bdiv(lp_amount, lp_supply)?;
+    balances
+        .iter()
+        .map(|balance| bnum::bmul(ratio, *balance))
+        .collect()
+}
+
+/// Largest amount of LP tokens `joinPool` mints without pulling more than
+/// `amounts` from the settlement, for a pool with `balances` at the time of
+/// the join.
+///
+/// The pool charges `bmul(bdiv(lp_amount, lp_supply), balance)` of every
+/// token, so this inverts both roundings instead of using plain ratios.
+pub fn lp_out_for_join(
+    amounts: &[U256],
+    balances: &[U256],
+    lp_supply: U256,
+) -> Result<U256, BNumError> {
+    let half_bone = BONE / 2;
+    let mut max_ratio = U256::MAX;
+    for (amount, balance) in amounts.iter().zip(balances) {
+        if balance.is_zero() {
+            return Err(BNumError::DivZero);
+        }
+        // Largest ratio with `bmul(ratio, balance) <= amount`.
+        let scaled = amount
+            .checked_mul(BONE)
+            .and_then(|scaled| scaled.checked_add(half_bone - 1))
+            .ok_or(BNumError::MulOverflow)?;
+        max_ratio = max_ratio.min(scaled / balance);
+    }
+
+    // Largest LP amount with `bdiv(lp_amount, lp_supply) <= max_ratio`.
+    let bound = (max_ratio + 1)
+        .checked_mul(lp_supply)
+        .ok_or(BNumError::MulOverflow)?;
+    match bound.checked_sub(lp_supply / 2 + 1) {
+        Some(bound) => Ok(bound / BONE),
+        None => Ok(U256::zero()),
+    }
+}
+
+/// Amount of the output token bought by swapping `amount_in` into the pool,
+/// like `calcOutGivenIn` without a fee.
+pub fn out_given_in(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_in: U256,
+) -> Result<U256, BNumError> {
+    let base = bnum::bdiv(balance_in, bnum::badd(balance_in, amount_in)?)?;
+    let exponent = bnum::bdiv(weight_in, weight_out)?;
+    let share = bnum::bsub(BONE, bnum::bpow(base, exponent)?)?;
+    bnum::bmul(balance_out, share)
+}
+
+/// Amount of the input token needed to buy `amount_out` from the pool, like
+/// `calcInGivenOut` without a fee.
+pub fn in_given_out(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_out: U256,
+) -> Result<U256, BNumError> {
+    let base = bnum::bdiv(balance_out, bnum::bsub(balance_out, amount_out)?)?;
+    let exponent = bnum::bdiv(weight_out, weight_in)?;
+    let growth = bnum::bsub(bnum::bpow(base, exponent)?, BONE)?;
+    bnum::bmul(balance_in, growth)
+}
+
+/// Balance of a pool token before a swap that left it at `balance_after`,
+/// given that the swap moved the balance of the other token from
+/// `other_before` to `other_after`.
+///
+/// Swaps keep `balance ^ weight * other ^ other_weight` constant, so
+/// `balance_before = balance_after * (other_after / other_before) ^ (other_weight / weight)`.
+/// This sizes swaps that have to end at a known balance.
+pub fn balance_before_swap(
+    balance_after: U256,
+    weight: U256,
+    other_before: U256,
+    other_after: U256,
+    other_weight: U256,
+) -> Result<U256, BNumError> {
+    let base = bnum::bdiv(other_after, other_before)?;
+    let exponent = bnum::bdiv(other_weight, weight)?;
+    bnum::bmul(balance_after, bnum::bpow(base, exponent)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, proptest::prelude::*};
+
+    fn u256(value: u128) -> U256 {
+        U256::from(value)
+    }
+
+    /// Tokens `joinPool` pulls for minting `lp_amount`.
+    fn join_amount(lp_amount: U256, lp_supply: U256, balance: U256) -> U256 {
+        proportional_amounts(lp_amount, lp_supply, &[balance]).unwrap()[0]
+    }
+
+    #[test]
+    fn swaps_share_of_input_for_balanced_pool() {
+        // Joining a 50/50 pool of 1000/1000 with 210 swaps 1000 * (sqrt(1.21) - 1)
+        // = 100, buys 1000 - 1000 * 1000 / 1100 ~ 90.9 and joins with 110 and
+        // 90.9, which are both 10% of the pool after the swap.
+        let swap = swap_amount_for_join(
+            u256(210) * BONE,
+            u256(1000) * BONE,
+            BONE,
+            u256(2) * BONE,
+        )
+        .unwrap();
+        assert_eq!(swap, u256(99_999_999_991_934_267_000));
+    }
+
+    #[test]
+    fn mints_lp_for_limiting_token() {
+        let lp_amount = lp_out_for_join(
+            &[u256(100) * BONE, u256(50) * BONE],
+            &[u256(1000) * BONE, u256(1000) * BONE],
+            u256(100) * BONE,
+        )
+        .unwrap();
+        // The rounding of the pool allows minting slightly more than the
+        // plain ratio of 5.
+        assert_eq!(lp_amount, u256(5_000_000_000_000_000_049));
+        assert_eq!(
+            join_amount(lp_amount, u256(100) * BONE, u256(1000) * BONE),
+            u256(50) * BONE
+        );
+
+        let lp_amount = lp_out_for_join(&[U256::zero()], &[BONE], BONE).unwrap();
+        assert_eq!(lp_amount, U256::zero());
+    }
+
+    #[test]
+    fn sizes_lp_amount_for_exact_token_out() {
+        // Receiving 10% of a token from a 50/50 pool burns
+        // `1 - sqrt(0.9)` of the supply.
+        let lp_amount = lp_in_for_exact_token_out(
+            U256::exp10(20),
+            U256::exp10(21),
+            U256::exp10(18),
+            U256::exp10(18) * 2,
+            U256::exp10(20),
+        )
+        .unwrap();
+        assert_eq!(lp_amount, U256::from(5_131_670_194_847_106_900_u64));
+
+        // The entire balance can not be bought out of the pool.
+        let result = lp_in_for_exact_token_out(
+            U256::exp10(21),
+            U256::exp10(21),
+            U256::exp10(18),
+            U256::exp10(18) * 2,
+            U256::exp10(20),
+        );
+        assert_eq!(result, Err(BNumError::BPowBaseTooLow));
+    }
+
+    #[test]
+    fn buys_enough_for_join_after_purchase() {
+        // Minting 10% of the supply after buying `x` needs `0.1 * (1000 - x)`,
+        // so `x = 1000 / 11`.
+        let lp_supply = u256(100) * BONE;
+        let balance = u256(1000) * BONE;
+        let bought = swap_out_for_join(u256(10) * BONE, balance, lp_supply).unwrap();
+        assert_eq!(bought, u256(90_909_090_909_090_909_091));
+        // The join uses up exactly what was bought.
+        let pulled = proportional_amounts(u256(10) * BONE, lp_supply, &[balance - bought]).unwrap();
+        assert_eq!(pulled, vec![bought]);
+    }
+
+    #[test]
+    fn sizes_swaps_of_balanced_pool() {
+        // Buying 100 of 1000 needs `1000 * (1000 / 900 - 1)` ~ 111.1, which
+        // buys exactly 100 again.
+        let (balance, weight) = (u256(1000) * BONE, BONE);
+        let amount_in = in_given_out(balance, weight, balance, weight, u256(100) * BONE).unwrap();
+        assert_eq!(amount_in, u256(111_111_111_111_111_111_000));
+        assert_eq!(
+            out_given_in(balance, weight, balance, weight, amount_in).unwrap(),
+            u256(100) * BONE
+        );
+
+        // Undoing the swap recovers the balance up to rounding.
+        let before = balance_before_swap(
+            balance + amount_in,
+            weight,
+            balance,
+            balance - u256(100) * BONE,
+            weight,
+        )
+        .unwrap();
+        assert_eq!(before, u256(999_999_999_999_999_999_900));
+    }
+
+    prop_compose! {
+        /// Two token pool with balances between 1e6 and 1e30 and weights
+        /// between the pool's `MIN_WEIGHT` and `MAX_WEIGHT`, and an input of up
+        /// to half of the input token balance.
+        fn join(
+        )(
+            balance_in in 1_000_000_u128..1_000_000_000_000_000_000_000_000_000_000,
+            balance_out in 1_000_000_u128..1_000_000_000_000_000_000_000_000_000_000,
+            weight_in in 1_u128..=50,
+            weight_out in 1_u128..=50,
+            lp_supply in 1_000_000_u128..1_000_000_000_000_000_000_000_000_000_000,
+            input_share in 1_u128..500_000,
+        ) -> (U256, U256, U256, U256, U256, U256) {
+            (
+                u256(balance_in),
+                u256(weight_in) * BONE,
+                u256(balance_out),
+                u256(weight_out) * BONE,
+                u256(lp_supply),
+                u256(balance_in) * u256(input_share) / u256(1_000_000),
+            )
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn join_amounts_are_proportional(
+            (balance_in, weight_in, balance_out, weight_out, lp_supply, amount_in) in join()
+        ) {
+            let total_weight = weight_in + weight_out;
+            let swap = swap_amount_for_join(amount_in, balance_in, weight_in, total_weight)
+                .unwrap();
+            prop_assert!(swap <= amount_in);
+
+            let bought =
+                out_given_in(balance_in, weight_in, balance_out, weight_out, swap).unwrap();
+            let amounts = [amount_in - swap, bought];
+            let balances = [balance_in + swap, balance_out - bought];
+
+            // Both tokens are used up to the precision of `bpow` and the
+            // rounding of the amounts to whole units.
+            let ratio_in = bnum::bdiv(amounts[0], balances[0]).unwrap();
+            let ratio_out = bnum::bdiv(amounts[1], balances[1]).unwrap();
+            let diff = bnum::bsub_sign(ratio_in, ratio_out).0;
+            let tolerance = bnum::bmul(ratio_in, u256(100_000_000_000)).unwrap()
+                + BONE * 4 / balances[0].min(balances[1])
+                + 2;
+            prop_assert!(diff <= tolerance, "{ratio_in} {ratio_out}");
+
+            // The join never pulls more than available, and one more LP token
+            // would need more of at least one of them.
+            let lp_amount = lp_out_for_join(&amounts, &balances, lp_supply).unwrap();
+            for (amount, balance) in amounts.iter().zip(&balances) {
+                prop_assert!(join_amount(lp_amount, lp_supply, *balance) <= *amount);
+            }
+            prop_assert!(amounts.iter().zip(&balances).any(|(amount, balance)| {
+                join_amount(lp_amount + 1, lp_supply, *balance) > *amount
+            }));
+        }
+    }
+}
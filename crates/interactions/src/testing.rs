@@ -0,0 +1,90 @@
+//! Fixtures shared by the tests of the encoders.
+
+use {
+    ethcontract::{Address, Bytes as SolBytes, U256},
+    hex_literal::hex,
+    tycho_simulation::{
+        evm::protocol::cowamm::state::CowAMMState,
+        foundry_evm::revm::primitives::U256 as AlloyU256,
+        tycho_common::Bytes,
+    },
+};
+
+// `GPv2Order.KIND_SELL` and `GPv2Order.BALANCE_ERC20`.
+const KIND_SELL: [u8; 32] =
+    hex!("f3b277728b3fee749481eb3e0b3b48980dbbab78658fc419025cb16eee346775");
+const BALANCE_ERC20: [u8; 32] =
+    hex!("5a28e9363bb942b639270062aa6bb295f434bcdfc42c97267bf003f272060dc9");
+
+pub type HelperOrder = (
+    Address,
+    Address,
+    Address,
+    U256,
+    U256,
+    u32,
+    SolBytes<[u8; 32]>,
+    U256,
+    SolBytes<[u8; 32]>,
+    bool,
+    SolBytes<[u8; 32]>,
+    SolBytes<[u8; 32]>,
+);
+pub type HelperInteraction = (Address, U256, SolBytes<Vec<u8>>);
+pub type HelperResponse = (
+    HelperOrder,
+    Vec<HelperInteraction>,
+    Vec<HelperInteraction>,
+    SolBytes<Vec<u8>>,
+);
+
+pub fn address_bytes(address: Address) -> Bytes {
+    Bytes::from(address.as_bytes().to_vec())
+}
+
+/// 50/50 pool with 1000 of each token and 100 LP tokens, which are issued by
+/// the pool itself.
+pub fn pool_state(pool: Address, token_a: Address, token_b: Address) -> CowAMMState {
+    CowAMMState::new(
+        address_bytes(pool),
+        address_bytes(token_a),
+        address_bytes(token_b),
+        AlloyU256::from(1_000_000_000_000_000_000_000_u128),
+        AlloyU256::from(1_000_000_000_000_000_000_000_u128),
+        address_bytes(pool),
+        AlloyU256::from(100_000_000_000_000_000_000_u128),
+        AlloyU256::from(1_000_000_000_000_000_000_u128),
+        AlloyU256::from(1_000_000_000_000_000_000_u128),
+        0,
+    )
+}
+
+/// Response of `BCowHelper.orderFromSellAmount` or `orderFromBuyAmount` for
+/// `pool`, with a commitment pre-interaction.
+pub fn helper_response(
+    pool: Address,
+    sell_token: Address,
+    buy_token: Address,
+    sell_amount: U256,
+    buy_amount: U256,
+) -> HelperResponse {
+    let order = (
+        sell_token,
+        buy_token,
+        pool,
+        sell_amount,
+        buy_amount,
+        u32::MAX,
+        SolBytes([0x42; 32]),
+        U256::zero(),
+        SolBytes(KIND_SELL),
+        true,
+        SolBytes(BALANCE_ERC20),
+        SolBytes(BALANCE_ERC20),
+    );
+    let commit = (pool, U256::zero(), SolBytes(vec![0x01, 0x02, 0x03, 0x04]));
+    // The helper prefixes the signature with the pool address.
+    let signature = [pool.as_bytes(), &[0xff; 32]].concat();
+
+    (order, vec![commit], vec![], SolBytes(signature))
+}
@@ -0,0 +1,73 @@
+use {
+    contracts::WETH9,
+    ethcontract::tokens::Bytes,
+    primitive_types::U256,
+    shared::interaction::{EncodedInteraction, Interaction},
+};
+
+/// Wraps `amount` of the settlement contract's ETH into WETH.
+#[derive(Clone, Debug)]
+pub struct WethDepositInteraction {
+    pub weth: WETH9,
+    pub amount: U256,
+}
+
+impl Interaction for WethDepositInteraction {
+    fn encode(&self) -> EncodedInteraction {
+        let method = self.weth.deposit();
+        let calldata = method.tx.data.expect("no calldata").0;
+        (self.weth.address(), self.amount, Bytes(calldata))
+    }
+}
+
+/// Unwraps `amount` of the settlement contract's WETH into ETH.
+#[derive(Clone, Debug)]
+pub struct WethWithdrawInteraction {
+    pub weth: WETH9,
+    pub amount: U256,
+}
+
+impl Interaction for WethWithdrawInteraction {
+    fn encode(&self) -> EncodedInteraction {
+        let method = self.weth.withdraw(self.amount);
+        let calldata = method.tx.data.expect("no calldata").0;
+        (self.weth.address(), 0.into(), Bytes(calldata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, contracts::dummy_contract, hex_literal::hex, primitive_types::H160};
+
+    #[test]
+    fn encode_deposit() {
+        let interaction = WethDepositInteraction {
+            weth: dummy_contract!(WETH9, [0x01; 20]),
+            amount: 1_000.into(),
+        };
+
+        let (to, value, data) = interaction.encode();
+        assert_eq!(to, H160([0x01; 20]));
+        assert_eq!(value, 1_000.into());
+        assert_eq!(data.0, hex!("d0e30db0"));
+    }
+
+    #[test]
+    fn encode_withdraw() {
+        let interaction = WethWithdrawInteraction {
+            weth: dummy_contract!(WETH9, [0x01; 20]),
+            amount: 1_000.into(),
+        };
+
+        let (to, value, data) = interaction.encode();
+        assert_eq!(to, H160([0x01; 20]));
+        assert_eq!(value, U256::zero());
+        assert_eq!(
+            data.0,
+            hex!(
+                "2e1a7d4d"
+                "00000000000000000000000000000000000000000000000000000000000003e8"
+            )
+        );
+    }
+}
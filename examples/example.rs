@@ -2,6 +2,7 @@ use std::sync::{Arc, LazyLock};
 use ethcontract::{Address, H256, H160, U256};
 use chrono::Utc;
 use ethrpc::http::HttpTransport;
+use ethcontract::web3::{transports::Http, Web3};
 use std::time::Duration;
 use std::str::FromStr;
 use hex;
@@ -20,7 +21,7 @@ use {
     num::BigUint,
     cow_amm::helper::Amm,
     interactions::{
-        encode_cowamm::{encode_cowamm, PoolState},
+        encode_cowamm::{CowAmmEncoder, PoolState},
         join_pool::JoinPoolInteraction, exit_pool::ExitPoolInteraction
     },
     contracts::{contract, BCowPool, BCowHelper},
@@ -119,8 +120,12 @@ async fn main() {
 
     let new_state: Arc<dyn PoolState> = Arc::new(pool_state);
 
+    //the encoder is created once for a node connection and reused for every encoding
+    let web3 = Web3::new(Http::new("https://ethereum-rpc.publicnode.com").unwrap());
+    let encoder = CowAmmEncoder::new(&web3, 1).unwrap();
+
     //returns a template order
-    let template = encode_cowamm(amount_in, token_in.address, token_out.address, new_state).await.unwrap();
+    let template = encoder.encode(OrderKind::Sell, amount_in, token_in.address, token_out.address, new_state).await.unwrap();
 
     // Get tokens traded by this AMM
     // let tokens = amm.traded_tokens();
@@ -141,7 +146,7 @@ async fn main() {
                     .unwrap();
 
     //post order to api 
-    let ob_api = OrderBookApi::new(client, MAINNET_PROD);
+    let ob_api = OrderBookApi::new(client, MAINNET_PROD).expect("valid orderbook URL");
     
      //https://github.com/cowprotocol/services/blob/d884bbe4db35f6d48f53cfeef856a72d7f50d302/crates/model/src/order.rs#L48
     let signing_scheme = EcdsaSigningScheme::Eip712;